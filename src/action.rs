@@ -0,0 +1,277 @@
+/*
+meml – XML replacement written in Rust with the pest library <https://pest.rs>.
+Developed to be used in ygo_destiny <https://github.com/myuujiku/ygo_destiny/>.
+Copyright (C) 2022  myujiku
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published
+by the Free Software Foundation, either version 3 of the License,
+or (at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+//! Output actions of manifest sections, selected by their `action` property.
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    Attribute, Element, Indent, JsonSerializer, Serializer, TomlSerializer, XmlOptions,
+    XmlSerializer, YamlSerializer,
+};
+
+/// Properties every section accepts regardless of its action.
+pub(crate) const COMMON_PROPERTIES: [&str; 5] =
+    ["action", "directory", "file", "change_extension", "target"];
+
+/// A section of a manifest.
+#[derive(Clone, Copy, Debug)]
+pub struct Section<'a> {
+    element: &'a Element,
+    root_dir: &'a Path,
+}
+
+impl<'a> Section<'a> {
+    pub(crate) fn new(element: &'a Element, root_dir: &'a Path) -> Self {
+        Self { element, root_dir }
+    }
+
+    pub fn name(&self) -> &'a str {
+        self.element.name()
+    }
+
+    /// All properties in the order they appear.
+    pub fn attributes(&self) -> &'a [Attribute] {
+        self.element.attributes()
+    }
+
+    /// Value of `property`. If it is set more than once, the last value is
+    /// used.
+    pub fn get(&self, property: &str) -> Option<&'a str> {
+        self.values(property).last()
+    }
+
+    /// All values of `property` in the order they appear, e.g. of `file`.
+    pub fn values<'b>(&self, property: &'b str) -> impl Iterator<Item = &'a str> + 'b
+    where
+        'a: 'b,
+    {
+        self.element
+            .attributes()
+            .iter()
+            .filter(move |attribute| attribute.qualified_name() == property)
+            .map(|attribute| attribute.value())
+    }
+
+    /// Directory of the manifest, which relative paths are resolved against.
+    pub fn root_dir(&self) -> &'a Path {
+        self.root_dir
+    }
+}
+
+/// Turns the evaluated elements of each file in a section into an output
+/// file.
+pub trait Action {
+    /// Properties the action accepts in addition to `action`, `directory`,
+    /// `file`, `change_extension` and `target`.
+    fn properties(&self) -> &[&str] {
+        &[]
+    }
+
+    /// Checks the properties of `section` before any of its files are
+    /// evaluated and returns the serializer for their output, or `None` if
+    /// nothing is written.
+    ///
+    /// An error is reported as [`MemlError::InvalidManifest`](crate::MemlError::InvalidManifest)
+    /// with the returned message.
+    fn serializer(&self, section: &Section) -> Result<Option<Box<dyn Serializer>>, String>;
+
+    /// Path of the output for the file at `source`. The default is the file
+    /// stem with the `change_extension` extension, or `meml` if it is not
+    /// set, in the `target` directory.
+    fn target_path(&self, section: &Section, source: &Path) -> PathBuf {
+        // File names are not necessarily UTF-8
+        let file_name = Path::new(source.file_name().unwrap_or_default())
+            .with_extension(section.get("change_extension").unwrap_or("meml"));
+
+        section
+            .root_dir()
+            .join(section.get("target").unwrap_or_default())
+            .join(file_name)
+    }
+}
+
+/// Actions that manifest sections can use, by name.
+pub struct Actions {
+    actions: HashMap<String, Box<dyn Action>>,
+}
+
+impl Default for Actions {
+    /// The built-in actions `xml`, `json`, `yaml`, `toml` and `none`.
+    fn default() -> Self {
+        let mut actions = Self::empty();
+        actions.register("xml", XmlAction);
+        actions.register("json", JsonAction);
+        actions.register("yaml", YamlAction);
+        actions.register("toml", TomlAction);
+        actions.register("none", NoneAction);
+        actions
+    }
+}
+
+impl Actions {
+    /// The built-in actions, see [`Actions::default`].
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// No actions, not even the built-in ones.
+    pub fn empty() -> Self {
+        Self {
+            actions: HashMap::new(),
+        }
+    }
+
+    /// Adds `action` under `name`, replacing any action with the same name.
+    pub fn register<A: Action + 'static>(&mut self, name: &str, action: A) {
+        self.actions.insert(name.to_string(), Box::new(action));
+    }
+
+    pub fn get(&self, name: &str) -> Option<&dyn Action> {
+        self.actions.get(name).map(|action| action.as_ref())
+    }
+
+    /// Names of all actions in alphabetical order.
+    pub fn names(&self) -> Vec<&str> {
+        let mut names = self
+            .actions
+            .keys()
+            .map(|name| name.as_str())
+            .collect::<Vec<&str>>();
+        names.sort_unstable();
+        names
+    }
+}
+
+/// Whether the `format` of `section` is `pretty`.
+fn is_pretty(section: &Section) -> Result<bool, String> {
+    match section.get("format").unwrap_or("compact") {
+        "compact" => Ok(false),
+        "pretty" => Ok(true),
+        format => Err(format!(
+            "Invalid format `{}`. Possible values: `compact`, `pretty`",
+            format
+        )),
+    }
+}
+
+/// Rejects the `pretty` format for actions that only write one layout.
+fn compact_only(section: &Section) -> Result<(), String> {
+    if is_pretty(section)? {
+        Err(format!(
+            "The `{}` action does not support the `pretty` format.",
+            section.get("action").unwrap_or_default()
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+struct XmlAction;
+
+impl Action for XmlAction {
+    fn properties(&self) -> &[&str] {
+        &["format", "indent"]
+    }
+
+    fn serializer(&self, section: &Section) -> Result<Option<Box<dyn Serializer>>, String> {
+        let pretty = is_pretty(section)?;
+
+        // `indent` is a number of spaces or `tab`
+        let indent = match section.get("indent") {
+            Some(_) if !pretty => {
+                return Err(
+                    "`indent` is only supported by the `xml` action with `format: \"pretty\"`."
+                        .to_string(),
+                )
+            }
+            None => Indent::Spaces(2),
+            Some("tab") => Indent::Tabs(1),
+            Some(spaces) => spaces.parse::<usize>().map(Indent::Spaces).map_err(|_| {
+                format!(
+                    "Invalid indent `{}`. Possible values: a number of spaces, `tab`",
+                    spaces
+                )
+            })?,
+        };
+
+        let options = if pretty {
+            XmlOptions {
+                indent: Some(indent),
+                ..XmlOptions::pretty()
+            }
+        } else {
+            XmlOptions::default()
+        };
+
+        Ok(Some(Box::new(XmlSerializer { options })))
+    }
+}
+
+struct JsonAction;
+
+impl Action for JsonAction {
+    fn properties(&self) -> &[&str] {
+        &["format"]
+    }
+
+    fn serializer(&self, section: &Section) -> Result<Option<Box<dyn Serializer>>, String> {
+        Ok(Some(Box::new(JsonSerializer {
+            pretty: is_pretty(section)?,
+        })))
+    }
+}
+
+struct YamlAction;
+
+impl Action for YamlAction {
+    fn properties(&self) -> &[&str] {
+        &["format"]
+    }
+
+    fn serializer(&self, section: &Section) -> Result<Option<Box<dyn Serializer>>, String> {
+        compact_only(section)?;
+        Ok(Some(Box::new(YamlSerializer)))
+    }
+}
+
+struct TomlAction;
+
+impl Action for TomlAction {
+    fn properties(&self) -> &[&str] {
+        &["format"]
+    }
+
+    fn serializer(&self, section: &Section) -> Result<Option<Box<dyn Serializer>>, String> {
+        compact_only(section)?;
+        Ok(Some(Box::new(TomlSerializer)))
+    }
+}
+
+/// Only evaluates the files, e.g. to check them for errors.
+struct NoneAction;
+
+impl Action for NoneAction {
+    fn serializer(&self, _section: &Section) -> Result<Option<Box<dyn Serializer>>, String> {
+        Ok(None)
+    }
+}
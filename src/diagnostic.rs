@@ -0,0 +1,289 @@
+/*
+meml – XML replacement written in Rust with the pest library <https://pest.rs>.
+Developed to be used in ygo_destiny <https://github.com/myuujiku/ygo_destiny/>.
+Copyright (C) 2022  myujiku
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published
+by the Free Software Foundation, either version 3 of the License,
+or (at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use crate::error::MemlError;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Error => write!(f, "error"),
+            Self::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub error: MemlError,
+}
+
+impl Diagnostic {
+    pub fn render(&self, style: Style) -> String {
+        render(self.severity, &self.error, style)
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.render(Style::Plain))
+    }
+}
+
+impl MemlError {
+    /// Renders the error with the source snippets of its locations.
+    pub fn render(&self, style: Style) -> String {
+        render(Severity::Error, self, style)
+    }
+}
+
+/// How diagnostics are rendered.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Style {
+    /// Plain text, e.g. for log files.
+    #[default]
+    Plain,
+    /// Text with ANSI color codes for terminals.
+    Colored,
+}
+
+impl Style {
+    fn paint(self, color: &str, text: &str) -> String {
+        match self {
+            Self::Plain => text.to_string(),
+            Self::Colored => format!("\x1b[{}m{}\x1b[0m", color, text),
+        }
+    }
+}
+
+const BLUE: &str = "1;34";
+const BOLD: &str = "1";
+
+fn render(severity: Severity, error: &MemlError, style: Style) -> String {
+    if let MemlError::Diagnostics(diagnostics) = error {
+        return diagnostics.render(style);
+    }
+
+    let color = match severity {
+        Severity::Error => "1;31",
+        Severity::Warning => "1;33",
+    };
+
+    let mut result = format!(
+        "{}{}\n",
+        style.paint(color, &severity.to_string()),
+        style.paint(BOLD, &format!(": {}", error.message()))
+    );
+
+    let labels = error.labels();
+    let width = labels
+        .iter()
+        .map(|label| label.location.line.to_string().len())
+        .max()
+        .unwrap_or(0);
+    let gutter = style.paint(BLUE, &format!("{:width$} |", "", width = width));
+
+    for label in labels {
+        let location = label.location;
+        let arrow = if label.primary { "-->" } else { ":::" };
+        result.push_str(&format!(
+            "{:width$}{} {}\n",
+            "",
+            style.paint(BLUE, arrow),
+            location,
+            width = width
+        ));
+
+        if location.snippet.is_empty() {
+            continue;
+        }
+
+        let snippet = &location.snippet;
+        let start = snippet
+            .char_indices()
+            .nth(location.column.saturating_sub(1))
+            .map_or(snippet.len(), |(index, _)| index);
+        let length = location.end.saturating_sub(location.start);
+
+        // Keep tabs so the underline lines up with the snippet
+        let padding = snippet[..start]
+            .chars()
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect::<String>();
+        let marker = if label.primary { "^" } else { "-" };
+        let mut underline = marker.repeat(
+            snippet[start..]
+                .char_indices()
+                .take_while(|(index, _)| *index < length)
+                .count()
+                .max(1),
+        );
+        if !label.message.is_empty() {
+            underline.push(' ');
+            underline.push_str(&label.message);
+        }
+
+        result.push_str(&format!(
+            "{}\n{} {}\n{} {}{}\n",
+            gutter,
+            style.paint(BLUE, &format!("{:>width$} |", location.line, width = width)),
+            snippet,
+            gutter,
+            padding,
+            style.paint(if label.primary { color } else { BLUE }, &underline)
+        ));
+    }
+
+    result
+}
+
+/// Collects errors and warnings so evaluation can continue past the first
+/// problem and report all of them at once.
+#[derive(Debug, Default)]
+pub struct Diagnostics {
+    diagnostics: Vec<Diagnostic>,
+    path: Option<PathBuf>,
+}
+
+impl Diagnostics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn error(&mut self, error: MemlError) {
+        self.push(Severity::Error, error);
+    }
+
+    pub fn warning(&mut self, error: MemlError) {
+        self.push(Severity::Warning, error);
+    }
+
+    fn push(&mut self, severity: Severity, error: MemlError) {
+        let error = match &self.path {
+            Some(path) => error.with_path(path),
+            None => error,
+        };
+
+        self.diagnostics.push(Diagnostic { severity, error });
+    }
+
+    /// Sets the file that diagnostics without a path are attributed to and
+    /// returns the previous one.
+    pub(crate) fn set_path(&mut self, path: Option<PathBuf>) -> Option<PathBuf> {
+        std::mem::replace(&mut self.path, path)
+    }
+
+    pub(crate) fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Diagnostic> {
+        self.diagnostics.iter()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.diagnostics.is_empty()
+    }
+
+    pub fn error_count(&self) -> usize {
+        self.count(Severity::Error)
+    }
+
+    pub fn warning_count(&self) -> usize {
+        self.count(Severity::Warning)
+    }
+
+    fn count(&self, severity: Severity) -> usize {
+        self.diagnostics
+            .iter()
+            .filter(|diagnostic| diagnostic.severity == severity)
+            .count()
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.error_count() > 0
+    }
+
+    /// Short summary such as `2 errors and 1 warning`.
+    pub fn summary(&self) -> String {
+        let plural = |count: usize, word: &str| {
+            format!("{} {}{}", count, word, if count == 1 { "" } else { "s" })
+        };
+
+        format!(
+            "{} and {}",
+            plural(self.error_count(), "error"),
+            plural(self.warning_count(), "warning")
+        )
+    }
+
+    /// Renders every diagnostic followed by the summary.
+    pub fn render(&self, style: Style) -> String {
+        let mut result = String::new();
+        for diagnostic in &self.diagnostics {
+            result.push_str(&diagnostic.render(style));
+            result.push('\n');
+        }
+        result.push_str(&style.paint(BOLD, &self.summary()));
+        result
+    }
+
+    /// Returns `value` if no errors were collected.
+    ///
+    /// A single error without any warnings is returned as is, anything else
+    /// is returned together as [`MemlError::Diagnostics`] so no warning is
+    /// lost.
+    pub fn into_result<T>(self, value: T) -> Result<T, MemlError> {
+        if self.has_errors() {
+            Err(self.into_error())
+        } else {
+            Ok(value)
+        }
+    }
+
+    pub(crate) fn into_error(mut self) -> MemlError {
+        if self.error_count() == 1 && self.warning_count() == 0 {
+            let index = self
+                .diagnostics
+                .iter()
+                .position(|diagnostic| diagnostic.severity == Severity::Error)
+                .unwrap();
+            self.diagnostics.swap_remove(index).error
+        } else {
+            MemlError::Diagnostics(self)
+        }
+    }
+}
+
+impl fmt::Display for Diagnostics {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.render(Style::Plain))
+    }
+}
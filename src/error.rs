@@ -0,0 +1,628 @@
+/*
+meml – XML replacement written in Rust with the pest library <https://pest.rs>.
+Developed to be used in ygo_destiny <https://github.com/myuujiku/ygo_destiny/>.
+Copyright (C) 2022  myujiku
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published
+by the Free Software Foundation, either version 3 of the License,
+or (at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use std::{
+    error, fmt, io,
+    path::{Path, PathBuf},
+};
+
+use pest::{
+    error::{InputLocation, LineColLocation},
+    Span,
+};
+
+use crate::{diagnostic::Diagnostics, parser::Rule};
+
+/// Position of a piece of meml source code.
+///
+/// `start` and `end` are byte offsets, `line` and `column` are 1-based.
+/// `snippet` is the source line the location starts on, so it can be shown
+/// without access to the whole source.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Location {
+    pub path: Option<PathBuf>,
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub column: usize,
+    pub snippet: String,
+}
+
+impl Location {
+    pub fn from_span(span: Span) -> Self {
+        let start = span.start_pos();
+        let (line, column) = start.line_col();
+
+        Self {
+            path: None,
+            start: span.start(),
+            end: span.end(),
+            line,
+            column,
+            snippet: start.line_of().trim_end_matches(['\n', '\r']).to_string(),
+        }
+    }
+
+    /// Sets the file path unless one is already set.
+    pub fn set_path(&mut self, path: &Path) {
+        if self.path.is_none() {
+            self.path = Some(path.to_path_buf());
+        }
+    }
+}
+
+/// Message attached to a location when rendering an error.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Label<'a> {
+    pub location: &'a Location,
+    pub message: String,
+    /// Whether this is the location the error occurred at, as opposed to a
+    /// related location such as a definition.
+    pub primary: bool,
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(path) = &self.path {
+            write!(f, "{}:", path.display())?;
+        }
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+/// Every error that can occur while parsing meml files or running a manifest.
+#[derive(Debug)]
+pub enum MemlError {
+    /// The source does not match the meml grammar.
+    Syntax { message: String, location: Location },
+    /// `$(name)` refers to a string constant that does not exist.
+    UndefinedString { name: String, location: Location },
+    /// An element constant that does not exist is used as a child.
+    UndefinedElement { name: String, location: Location },
+    /// A function that does not exist is called.
+    UndefinedFunction {
+        name: String,
+        /// The most similar defined function, if any is similar enough.
+        suggestion: Option<String>,
+        location: Location,
+    },
+    /// `[name]` refers to a list that does not exist.
+    UndefinedList { name: String, location: Location },
+    /// `prefix:name` uses a namespace prefix without a `namespace` declaration.
+    UndefinedNamespace { prefix: String, location: Location },
+    /// `namespace prefix = "uri"` declares a reserved prefix or namespace, or
+    /// a prefix that is already declared, or an attribute is called `xmlns`
+    /// or has the `xmlns` prefix.
+    InvalidNamespace { reason: String, location: Location },
+    /// `${name}` refers to an argument the enclosing function does not have.
+    UndefinedArgument { name: String, location: Location },
+    /// `${name}` is used outside of a function body.
+    UnexpectedArgument { name: String, location: Location },
+    /// Text ends up outside of any element, e.g. in a top-level `if`.
+    UnexpectedText { location: Location },
+    /// `use <type> name` refers to a definition the manifest does not export.
+    UndefinedImport {
+        def_type: String,
+        name: String,
+        available: Vec<String>,
+        location: Location,
+    },
+    /// `use <type> name` would replace a definition that already exists.
+    DuplicateDefinition {
+        def_type: String,
+        name: String,
+        location: Location,
+    },
+    /// A local definition replaces an earlier one with the same name.
+    Redefinition {
+        def_type: String,
+        name: String,
+        location: Location,
+    },
+    /// A function is called with the wrong number of arguments.
+    ArityMismatch {
+        name: String,
+        /// The function name with its parameters, e.g. `repeat(text)`.
+        signature: String,
+        expected: usize,
+        found: usize,
+        location: Location,
+        definition: Location,
+    },
+    /// A parameter without a default value is not supplied.
+    MissingArgument {
+        name: String,
+        signature: String,
+        location: Location,
+        definition: Location,
+    },
+    /// A named argument does not match any parameter of the function.
+    UnknownArgument {
+        name: String,
+        signature: String,
+        suggestion: Option<String>,
+        location: Location,
+        definition: Location,
+    },
+    /// A parameter is supplied both by position and by name, or twice by name.
+    DuplicateArgument { name: String, location: Location },
+    /// An element has several attributes with the same qualified name.
+    DuplicateAttribute { name: String, location: Location },
+    /// An element, attribute or namespace prefix is not a valid XML name.
+    InvalidName {
+        kind: String,
+        name: String,
+        location: Location,
+    },
+    /// A `\u{...}` escape is not a valid unicode character.
+    InvalidEscape {
+        sequence: String,
+        location: Location,
+    },
+    /// Text or an attribute value contains a character that XML does not
+    /// allow, even as a character reference.
+    InvalidCharacter { character: char, location: Location },
+    /// A bound of a `for` range is not an integer.
+    InvalidNumber { value: String, location: Location },
+    /// An element constant or function uses itself, directly or through other
+    /// constants and functions. `path` lists them from the first use to the
+    /// repeated one.
+    RecursionCycle {
+        path: Vec<String>,
+        location: Location,
+    },
+    /// Evaluating a document exceeds one of its [`Limits`](crate::Limits).
+    LimitExceeded {
+        limit: String,
+        max: usize,
+        location: Location,
+    },
+    /// A file or directory could not be read or written.
+    Io { path: PathBuf, error: io::Error },
+    /// A manifest section has a property that is not recognised.
+    InvalidManifestProperty {
+        path: PathBuf,
+        section: String,
+        property: String,
+        /// Properties the section accepts with its action.
+        expected: Vec<String>,
+    },
+    /// A manifest section is incomplete or has an invalid value.
+    InvalidManifest {
+        path: PathBuf,
+        section: String,
+        message: String,
+    },
+    /// The evaluated elements cannot be written in an output format, e.g. an
+    /// array that mixes text and tables in TOML.
+    UnsupportedOutput {
+        /// The file the elements came from, if known.
+        path: Option<PathBuf>,
+        format: String,
+        /// Dotted path of the offending key.
+        key: String,
+        reason: String,
+    },
+    /// Several errors collected during a single run.
+    Diagnostics(Diagnostics),
+}
+
+impl MemlError {
+    pub fn io(path: &Path, error: io::Error) -> Self {
+        Self::Io {
+            path: path.to_path_buf(),
+            error,
+        }
+    }
+
+    /// Returns the source location of the error, if it has one.
+    pub fn location(&self) -> Option<&Location> {
+        match self {
+            Self::Syntax { location, .. }
+            | Self::UndefinedString { location, .. }
+            | Self::UndefinedElement { location, .. }
+            | Self::UndefinedFunction { location, .. }
+            | Self::UndefinedList { location, .. }
+            | Self::UndefinedNamespace { location, .. }
+            | Self::InvalidNamespace { location, .. }
+            | Self::UndefinedArgument { location, .. }
+            | Self::UnexpectedArgument { location, .. }
+            | Self::UnexpectedText { location }
+            | Self::UndefinedImport { location, .. }
+            | Self::DuplicateDefinition { location, .. }
+            | Self::Redefinition { location, .. }
+            | Self::ArityMismatch { location, .. }
+            | Self::MissingArgument { location, .. }
+            | Self::UnknownArgument { location, .. }
+            | Self::DuplicateArgument { location, .. }
+            | Self::DuplicateAttribute { location, .. }
+            | Self::InvalidName { location, .. }
+            | Self::InvalidEscape { location, .. }
+            | Self::InvalidCharacter { location, .. }
+            | Self::InvalidNumber { location, .. }
+            | Self::RecursionCycle { location, .. }
+            | Self::LimitExceeded { location, .. } => Some(location),
+            _ => None,
+        }
+    }
+
+    /// Locations to show when rendering the error, starting with the primary one.
+    pub fn labels(&self) -> Vec<Label<'_>> {
+        let primary = |location, message: &str| Label {
+            location,
+            message: message.to_string(),
+            primary: true,
+        };
+        let defined_here = |location| Label {
+            location,
+            message: "function defined here".to_string(),
+            primary: false,
+        };
+
+        match self {
+            Self::Syntax { location, .. } => vec![primary(location, "")],
+            Self::UndefinedString { location, .. }
+            | Self::UndefinedElement { location, .. }
+            | Self::UndefinedFunction { location, .. }
+            | Self::UndefinedList { location, .. } => vec![primary(location, "not defined")],
+            Self::UndefinedNamespace { location, .. } => vec![primary(location, "not declared")],
+            Self::InvalidNamespace { location, .. } => {
+                vec![primary(location, "invalid declaration")]
+            }
+            Self::UndefinedArgument { location, .. } => {
+                vec![primary(location, "not an argument of this function")]
+            }
+            Self::UnexpectedArgument { location, .. } => {
+                vec![primary(location, "only available inside functions")]
+            }
+            Self::UnexpectedText { location } => {
+                vec![primary(location, "text is only allowed inside elements")]
+            }
+            Self::UndefinedImport { location, .. } => {
+                vec![primary(location, "not exported by the manifest")]
+            }
+            Self::DuplicateDefinition { location, .. } => {
+                vec![primary(location, "already defined in this file")]
+            }
+            Self::Redefinition { location, .. } => vec![primary(location, "redefined here")],
+            Self::ArityMismatch {
+                expected,
+                location,
+                definition,
+                ..
+            } => vec![
+                primary(location, &format!("expected {} argument(s)", expected)),
+                defined_here(definition),
+            ],
+            Self::MissingArgument {
+                name,
+                location,
+                definition,
+                ..
+            } => vec![
+                primary(location, &format!("missing `{}`", name)),
+                defined_here(definition),
+            ],
+            Self::UnknownArgument {
+                location,
+                definition,
+                ..
+            } => vec![
+                primary(location, "unknown parameter"),
+                defined_here(definition),
+            ],
+            Self::DuplicateArgument { location, .. } => {
+                vec![primary(location, "already supplied")]
+            }
+            Self::DuplicateAttribute { location, .. } => {
+                vec![primary(location, "already set on this element")]
+            }
+            Self::InvalidName { location, .. } => {
+                vec![primary(location, "must start with a letter or `_`")]
+            }
+            Self::InvalidEscape { location, .. } => {
+                vec![primary(location, "not a unicode scalar value")]
+            }
+            Self::InvalidCharacter { location, .. } => {
+                vec![primary(location, "not allowed in XML")]
+            }
+            Self::InvalidNumber { location, .. } => vec![primary(location, "expected an integer")],
+            Self::RecursionCycle { location, .. } => vec![primary(location, "used recursively")],
+            Self::LimitExceeded { location, .. } => {
+                vec![primary(
+                    location,
+                    "limit exceeded while expanding this element",
+                )]
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    /// Describes the error without its location.
+    pub fn message(&self) -> String {
+        match self {
+            Self::Syntax { message, .. } => message.to_string(),
+            Self::UndefinedString { name, .. } => {
+                format!("undefined string constant `{}`", name)
+            }
+            Self::UndefinedElement { name, .. } => {
+                format!("undefined element constant `{}`", name)
+            }
+            Self::UndefinedFunction {
+                name, suggestion, ..
+            } => format!("undefined function `{}`{}", name, did_you_mean(suggestion)),
+            Self::UndefinedList { name, .. } => format!("undefined list `{}`", name),
+            Self::UndefinedNamespace { prefix, .. } => format!(
+                "undeclared namespace prefix `{}` (declare it with `namespace {} = \"...\"`)",
+                prefix, prefix
+            ),
+            Self::InvalidNamespace { reason, .. } => {
+                format!("invalid namespace declaration: {}", reason)
+            }
+            Self::UndefinedArgument { name, .. } => format!("undefined argument `{}`", name),
+            Self::UnexpectedArgument { name, .. } => format!(
+                "unexpected function argument `{}` (to access a constant use parentheses instead)",
+                name
+            ),
+            Self::UnexpectedText { .. } => "text outside of an element".to_string(),
+            Self::UndefinedImport {
+                def_type,
+                name,
+                available,
+                ..
+            } => format!(
+                "undefined {} constant `{}`; available values: {}",
+                def_type,
+                name,
+                available
+                    .iter()
+                    .map(|item| format!("`{}`", item))
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            ),
+            Self::DuplicateDefinition { def_type, name, .. } => format!(
+                "{} constant `{}` is already defined; use `as` to include it under a different name",
+                def_type, name
+            ),
+            Self::Redefinition { def_type, name, .. } => format!(
+                "{} constant `{}` replaces an earlier definition",
+                def_type, name
+            ),
+            Self::ArityMismatch {
+                signature,
+                expected,
+                found,
+                ..
+            } => format!(
+                "function `{}` takes {} argument(s) but {} were supplied",
+                signature, expected, found
+            ),
+            Self::MissingArgument {
+                name, signature, ..
+            } => format!(
+                "function `{}` is missing an argument for `{}`",
+                signature, name
+            ),
+            Self::UnknownArgument {
+                name,
+                signature,
+                suggestion,
+                ..
+            } => format!(
+                "function `{}` has no parameter `{}`{}",
+                signature,
+                name,
+                did_you_mean(suggestion)
+            ),
+            Self::DuplicateArgument { name, .. } => {
+                format!("argument `{}` is supplied more than once", name)
+            }
+            Self::DuplicateAttribute { name, .. } => {
+                format!("attribute `{}` is set more than once", name)
+            }
+            Self::InvalidName { kind, name, .. } => {
+                format!("`{}` is not a valid XML {} name", name, kind)
+            }
+            Self::InvalidEscape { sequence, .. } => {
+                format!("invalid unicode escape `{}`", sequence)
+            }
+            Self::InvalidCharacter { character, .. } => {
+                format!("invalid character `U+{:04X}`", *character as u32)
+            }
+            Self::InvalidNumber { value, .. } => {
+                format!("`{}` is not a valid range bound", value)
+            }
+            Self::RecursionCycle { path, .. } => format!(
+                "`{}` uses itself: {}",
+                path[0],
+                path.iter()
+                    .map(|item| format!("`{}`", item))
+                    .collect::<Vec<String>>()
+                    .join(" -> ")
+            ),
+            Self::LimitExceeded { limit, max, .. } => {
+                format!("expansion exceeds the maximum {} of {}", limit, max)
+            }
+            Self::Io { path, error } => format!("{}: {}", path.display(), error),
+            Self::InvalidManifestProperty {
+                path,
+                section,
+                property,
+                expected,
+            } => format!(
+                "{}: unexpected property `{}` in section `{}`; expected one of {}",
+                path.display(),
+                property,
+                section,
+                expected
+                    .iter()
+                    .map(|item| format!("`{}`", item))
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            ),
+            Self::InvalidManifest {
+                path,
+                section,
+                message,
+            } => format!("{}: section `{}`: {}", path.display(), section, message),
+            Self::UnsupportedOutput {
+                path,
+                format,
+                key,
+                reason,
+            } => format!(
+                "{}cannot write `{}` as {}: {}",
+                path.as_ref()
+                    .map_or(String::new(), |path| format!("{}: ", path.display())),
+                key,
+                format,
+                reason
+            ),
+            Self::Diagnostics(diagnostics) => diagnostics.to_string(),
+        }
+    }
+
+    /// Sets the file path of the error unless one is already set.
+    pub fn with_path(mut self, path: &Path) -> Self {
+        let location = match &mut self {
+            Self::Syntax { location, .. }
+            | Self::UndefinedString { location, .. }
+            | Self::UndefinedElement { location, .. }
+            | Self::UndefinedFunction { location, .. }
+            | Self::UndefinedList { location, .. }
+            | Self::UndefinedNamespace { location, .. }
+            | Self::InvalidNamespace { location, .. }
+            | Self::UndefinedArgument { location, .. }
+            | Self::UnexpectedArgument { location, .. }
+            | Self::UnexpectedText { location }
+            | Self::UndefinedImport { location, .. }
+            | Self::DuplicateDefinition { location, .. }
+            | Self::Redefinition { location, .. }
+            | Self::ArityMismatch { location, .. }
+            | Self::MissingArgument { location, .. }
+            | Self::UnknownArgument { location, .. }
+            | Self::DuplicateArgument { location, .. }
+            | Self::DuplicateAttribute { location, .. }
+            | Self::InvalidName { location, .. }
+            | Self::InvalidEscape { location, .. }
+            | Self::InvalidCharacter { location, .. }
+            | Self::InvalidNumber { location, .. }
+            | Self::RecursionCycle { location, .. }
+            | Self::LimitExceeded { location, .. } => location,
+            Self::UnsupportedOutput { path: file, .. } => {
+                file.get_or_insert_with(|| path.to_path_buf());
+                return self;
+            }
+            Self::Io { path: file, .. } => {
+                if file.as_os_str().is_empty() {
+                    *file = path.to_path_buf();
+                }
+                return self;
+            }
+            _ => return self,
+        };
+
+        location.set_path(path);
+        self
+    }
+}
+
+fn did_you_mean(suggestion: &Option<String>) -> String {
+    match suggestion {
+        Some(suggestion) => format!("; did you mean `{}`?", suggestion),
+        None => String::new(),
+    }
+}
+
+/// Returns the candidate most similar to `name`, if any is close enough to be
+/// a likely typo.
+pub(crate) fn suggest<'a>(
+    name: &str,
+    candidates: impl IntoIterator<Item = &'a String>,
+) -> Option<String> {
+    let max_distance = (name.chars().count() / 3).max(1);
+
+    candidates
+        .into_iter()
+        .map(|candidate| (edit_distance(name, candidate), candidate))
+        .filter(|(distance, _)| *distance <= max_distance)
+        .min()
+        .map(|(_, candidate)| candidate.to_string())
+}
+
+/// Levenshtein distance between `a` and `b`.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<char>>();
+    let mut previous = (0..=b.len()).collect::<Vec<usize>>();
+
+    for (i, a_char) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a_char != *b_char);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+
+    previous[b.len()]
+}
+
+impl From<pest::error::Error<Rule>> for MemlError {
+    fn from(error: pest::error::Error<Rule>) -> Self {
+        let (start, end) = match error.location {
+            InputLocation::Pos(pos) => (pos, pos),
+            InputLocation::Span(span) => span,
+        };
+        let (line, column) = match error.line_col {
+            LineColLocation::Pos(pos) => pos,
+            LineColLocation::Span(pos, _) => pos,
+        };
+
+        Self::Syntax {
+            message: error.variant.message().to_string(),
+            location: Location {
+                path: None,
+                start,
+                end,
+                line,
+                column,
+                snippet: error.line().trim_end_matches(['\n', '\r']).to_string(),
+            },
+        }
+    }
+}
+
+impl fmt::Display for MemlError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(location) = self.location() {
+            write!(f, "{}: ", location)?;
+        }
+        write!(f, "{}", self.message())
+    }
+}
+
+impl error::Error for MemlError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Self::Io { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
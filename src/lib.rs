@@ -17,174 +17,393 @@ You should have received a copy of the GNU General Public License
 along with this program.  If not, see <https://www.gnu.org/licenses/>.
 */
 
+// Errors are only constructed on the cold path and carry their full location,
+// boxing them would only make matching on them more awkward.
+#![allow(clippy::result_large_err)]
+
 extern crate pest;
 #[macro_use]
 extern crate pest_derive;
 
+mod action;
+mod diagnostic;
+mod error;
 mod parser;
 
 use std::{
     collections::HashMap,
-    fs,
-    path::{Path, PathBuf},
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    path::Path,
 };
 
-pub fn parse_manifest(manifest_path: &str) {
-    let manifest_file = Path::new(manifest_path);
+use action::COMMON_PROPERTIES;
+
+pub use action::{Action, Actions, Section};
+pub use diagnostic::{Diagnostic, Diagnostics, Severity, Style};
+pub use error::{Label, Location, MemlError};
+pub use parser::{
+    to_json, to_toml, to_xml, to_yaml, Attribute, Definition, DefinitionMap, Definitions, Element,
+    ElementTemplate, Function, HashingWriter, Indent, JsonSerializer, Limits, LineEnding, Node,
+    Scope, Serializer, TomlSerializer, XmlOptions, XmlSerializer, YamlSerializer,
+};
+
+/// The result of evaluating a single meml source.
+#[derive(Clone, Debug, Default)]
+pub struct Document {
+    elements: Vec<Element>,
+    exports: DefinitionMap,
+}
 
-    // Panic if the Path is not a file
-    if !manifest_file.is_file() {
-        panic!("Manifest `{}` is not a file.", manifest_path);
+impl Document {
+    /// Top-level elements in the order they appear in the source.
+    pub fn elements(&self) -> &[Element] {
+        &self.elements
     }
 
-    let raw_content = fs::read_to_string(manifest_path).expect("Could not read manifest file.");
-    let manifest_rules = parser::parse_raw(&raw_content);
-
-    let (manifest_definitions, manifest_exports, manifest_contents) =
-        parser::get_definitions(manifest_rules, &HashMap::new());
-
-    let root_dir = manifest_file.parent().unwrap();
-
-    for section in parser::get_contents(manifest_contents, manifest_definitions) {
-        let mut action = String::new();
-        let mut directories = Vec::<String>::new();
-        let mut files = Vec::<String>::new();
-        let mut extension = String::new();
-        let mut target = String::new();
-
-        for (name, value) in section.arguments {
-            match name.as_str() {
-                "action" => action = value.to_string(),
-                "directory" => directories.push(value),
-                "file" => files.push(value),
-                "change_extension" => extension = value.to_string(),
-                "target" => target = value.to_string(),
-                _ => panic!(
-                    "Unexpected property `{}` in section `{}`. Expected one of `action`, `directory`, `file`, `change_extension` and `target`.",
-                    name,
-                    section.name,
-                ),
-            }
+    /// Definitions marked with `export`, grouped by category
+    /// (`strings`, `elements`, `functions` and `lists`).
+    pub fn exports(&self) -> &DefinitionMap {
+        &self.exports
+    }
+
+    pub fn into_exports(self) -> DefinitionMap {
+        self.exports
+    }
+
+    pub fn into_elements(self) -> Vec<Element> {
+        self.elements
+    }
+}
+
+/// Parses and evaluates meml source code.
+pub fn parse_str(source: &str) -> Result<Document, MemlError> {
+    parse_str_with_imports(source, &HashMap::new())
+}
+
+/// Reads the file at `path`, then parses and evaluates it.
+///
+/// Errors carry `path` in their location.
+pub fn parse_file<P: AsRef<Path>>(path: P) -> Result<Document, MemlError> {
+    parse_file_with_imports(path, &HashMap::new())
+}
+
+/// Like [`parse_str`], but `use <type> name` can include definitions from
+/// `imports`, usually the exports of another document.
+pub fn parse_str_with_imports(
+    source: &str,
+    imports: &DefinitionMap,
+) -> Result<Document, MemlError> {
+    let mut diagnostics = Diagnostics::new();
+    let document = parse_str_with_diagnostics(source, imports, Limits::default(), &mut diagnostics);
+    diagnostics.into_result(document)
+}
+
+/// Like [`parse_file`], but `use <type> name` can include definitions from
+/// `imports`, usually the exports of another document.
+pub fn parse_file_with_imports<P: AsRef<Path>>(
+    path: P,
+    imports: &DefinitionMap,
+) -> Result<Document, MemlError> {
+    let mut diagnostics = Diagnostics::new();
+    let document = parse_file_with_diagnostics(path, imports, Limits::default(), &mut diagnostics);
+    diagnostics.into_result(document)
+}
+
+/// Like [`parse_str_with_imports`], but all errors and warnings are added to
+/// `diagnostics` instead of stopping at the first error, and evaluation is
+/// bounded by `limits` instead of the default ones.
+///
+/// The returned document is incomplete if any errors were added.
+pub fn parse_str_with_diagnostics(
+    source: &str,
+    imports: &DefinitionMap,
+    limits: Limits,
+    diagnostics: &mut Diagnostics,
+) -> Document {
+    // Definitions and contents share the limits, since string constants
+    // can grow as much as the elements that use them
+    parse_source(
+        source,
+        imports,
+        &mut parser::Expansion::new(limits),
+        diagnostics,
+    )
+}
+
+fn parse_source(
+    source: &str,
+    imports: &DefinitionMap,
+    expansion: &mut parser::Expansion,
+    diagnostics: &mut Diagnostics,
+) -> Document {
+    let rules = match parser::parse_raw(source) {
+        Ok(rules) => rules,
+        Err(error) => {
+            diagnostics.error(error);
+            return Document::default();
         }
+    };
+    let (definitions, exports, contents) =
+        parser::get_definitions(rules, imports, expansion, diagnostics);
+
+    Document {
+        elements: parser::get_contents(contents, definitions, expansion, diagnostics),
+        exports,
+    }
+}
 
-        // Sort the files and remove duplicates
-        files.sort_unstable();
-        files.dedup();
+/// Like [`parse_file_with_imports`], but all errors and warnings are added to
+/// `diagnostics` instead of stopping at the first error, and evaluation is
+/// bounded by `limits` instead of the default ones.
+///
+/// The returned document is incomplete if any errors were added.
+pub fn parse_file_with_diagnostics<P: AsRef<Path>>(
+    path: P,
+    imports: &DefinitionMap,
+    limits: Limits,
+    diagnostics: &mut Diagnostics,
+) -> Document {
+    parse_path(
+        path.as_ref(),
+        imports,
+        &mut parser::Expansion::new(limits),
+        diagnostics,
+    )
+}
 
-        let is_action_none = action == "none";
+fn parse_path(
+    path: &Path,
+    imports: &DefinitionMap,
+    expansion: &mut parser::Expansion,
+    diagnostics: &mut Diagnostics,
+) -> Document {
+    match fs::read_to_string(path) {
+        Ok(source) => {
+            let previous = diagnostics.set_path(Some(path.to_path_buf()));
+            let mut document = parse_source(&source, imports, expansion, diagnostics);
+            diagnostics.set_path(previous);
 
-        if action.is_empty() {
-            panic!("Section `{}`: No action specified. Add `action: \"none\"` as a section property to disable this check.", section.name);
-        } else if target.is_empty() && !is_action_none {
-            panic!(
-                "Section `{}`: No target directory specified in.",
-                section.name
-            );
-        } else {
-            if !(directories.is_empty() && files.is_empty()) {
-                let mut file_paths = Vec::new();
-
-                for directory in directories {
-                    let path = root_dir.join(directory);
-                    if path.is_dir() {
-                        file_paths.append(
-                            &mut fs::read_dir(path)
-                                .unwrap()
-                                .map(|item| item.unwrap().path())
-                                .filter(|item| {
-                                    let ext = item.extension();
-                                    ext.is_some() && ext.unwrap() == "meml"
-                                })
-                                .collect(),
-                        );
-                    } else {
-                        panic!(
-                            "Section `{}`: Directory `{}` not found.",
-                            section.name,
-                            path.display()
-                        );
-                    }
+            // Exports are evaluated in other files, so they need to remember
+            // which file they came from
+            for definitions in document.exports.values_mut() {
+                for definition in definitions.values_mut() {
+                    definition.set_path(path);
                 }
+            }
 
-                file_paths.append(&mut files.iter().map(|item| root_dir.join(item)).collect());
+            document
+        }
+        Err(error) => {
+            diagnostics.error(MemlError::io(path, error));
+            Document::default()
+        }
+    }
+}
 
-                println!("{:#?}", file_paths);
+/// Runs every section of the manifest at `manifest_path` with the built-in
+/// actions.
+///
+/// Problems in one section or file do not stop the others from being
+/// processed, but files with errors are not written. Returns the collected
+/// warnings if there were no errors.
+pub fn parse_manifest(manifest_path: &str) -> Result<Diagnostics, MemlError> {
+    parse_manifest_with_actions(manifest_path, &Actions::default())
+}
 
-                if !is_action_none {
-                    fs::create_dir_all(root_dir.join(&target)).expect(&format!(
-                        "Section `{}`: Could not create target directories.",
-                        section.name
-                    ));
-                }
+/// Like [`parse_manifest`], but sections can use any action in `actions`.
+pub fn parse_manifest_with_actions(
+    manifest_path: &str,
+    actions: &Actions,
+) -> Result<Diagnostics, MemlError> {
+    let manifest_file = Path::new(manifest_path);
+    let mut diagnostics = Diagnostics::new();
+
+    // Sections can list several inputs with repeated `file` and `directory`
+    // properties
+    let mut expansion = parser::Expansion::new(Limits::default());
+    expansion.repeated_attributes = true;
+    let manifest = parse_path(
+        manifest_file,
+        &HashMap::new(),
+        &mut expansion,
+        &mut diagnostics,
+    );
+
+    // A path without a parent cannot be read, so there are no sections to run
+    let root_dir = manifest_file.parent().unwrap_or(Path::new(""));
 
-                for path in file_paths {
-                    let basename = path.file_stem().unwrap().to_str().unwrap();
-
-                    let raw_content = fs::read_to_string(&path).expect(&format!(
-                        "Section `{}`: Could not read file `{}`.",
-                        section.name,
-                        path.display()
-                    ));
-                    let rules = parser::parse_raw(&raw_content);
-
-                    let (definitions, exports, contents) =
-                        parser::get_definitions(rules, &manifest_exports);
-
-                    let elements = parser::get_contents(contents, definitions);
-
-                    let target_path = if !is_action_none {
-                        root_dir.join(&target).join(format!(
-                            "{}.{}",
-                            basename,
-                            if extension.is_empty() {
-                                ".meml"
-                            } else {
-                                &extension
-                            }
-                        ))
-                    } else {
-                        PathBuf::new()
-                    };
-
-                    match action.as_str() {
-                        "xml" => {
-                            let content = elements
-                                .iter()
-                                .map(|item| item.as_xml())
-                                .collect::<Vec<String>>()
-                                .join("");
-
-                            if !target_path.is_file()
-                                || (content != fs::read_to_string(&target_path).unwrap())
-                            {
-                                fs::write(&target_path, content).expect(&format!(
-                                    "Section `{}`: Could not write to `{}`",
-                                    section.name,
-                                    target_path.display()
-                                ));
-                            }
-                        }
-                        "none" => (),
-                        _ => panic!(
-                            "Section `{}`: Invalid action `{}`. Possible values: `xml`, `none`",
-                            section.name, action
-                        ),
-                    }
-
-                    println!(
-                        "{}",
-                        elements
-                            .iter()
-                            .map(|i| i.as_xml())
-                            .collect::<Vec<String>>()
-                            .join("")
-                    );
+    for section in manifest.elements() {
+        if let Err(error) = run_section(
+            Section::new(section, root_dir),
+            actions,
+            &manifest,
+            manifest_file,
+            &mut diagnostics,
+        ) {
+            diagnostics.error(error);
+        }
+    }
+
+    if diagnostics.has_errors() {
+        Err(diagnostics.into_error())
+    } else {
+        Ok(diagnostics)
+    }
+}
+
+fn run_section(
+    section: Section,
+    actions: &Actions,
+    manifest: &Document,
+    manifest_file: &Path,
+    diagnostics: &mut Diagnostics,
+) -> Result<(), MemlError> {
+    let invalid = |message: String| MemlError::InvalidManifest {
+        path: manifest_file.to_path_buf(),
+        section: section.name().to_string(),
+        message,
+    };
+
+    let Some(name) = section.get("action").filter(|name| !name.is_empty()) else {
+        return Err(invalid("No action specified. Add `action: \"none\"` as a section property to disable this check.".to_string()));
+    };
+    let Some(action) = actions.get(name) else {
+        return Err(invalid(format!(
+            "Invalid action `{}`. Possible values: {}",
+            name,
+            actions
+                .names()
+                .iter()
+                .map(|name| format!("`{}`", name))
+                .collect::<Vec<String>>()
+                .join(", ")
+        )));
+    };
+
+    let expected = COMMON_PROPERTIES
+        .iter()
+        .chain(action.properties())
+        .map(|property| property.to_string())
+        .collect::<Vec<String>>();
+    let mut valid = true;
+
+    for attribute in section.attributes() {
+        let property = attribute.qualified_name();
+        if !expected.contains(&property) {
+            diagnostics.error(MemlError::InvalidManifestProperty {
+                path: manifest_file.to_path_buf(),
+                section: section.name().to_string(),
+                property,
+                expected: expected.clone(),
+            });
+            valid = false;
+        }
+    }
+
+    let directories = section.values("directory").collect::<Vec<&str>>();
+    let mut files = section.values("file").collect::<Vec<&str>>();
+
+    // Sort the files and remove duplicates
+    files.sort_unstable();
+    files.dedup();
+
+    if directories.is_empty() && files.is_empty() {
+        return Err(invalid("No input specified. Please add one or more of either `file` or `directory` as a property.".to_string()));
+    }
+
+    let serializer = action.serializer(&section).map_err(invalid)?;
+
+    if serializer.is_some() && section.get("target").unwrap_or_default().is_empty() {
+        return Err(invalid("No target directory specified.".to_string()));
+    } else if !valid {
+        return Ok(());
+    }
+
+    let root_dir = section.root_dir();
+    let mut file_paths = Vec::new();
+
+    for directory in directories {
+        let path = root_dir.join(directory);
+        if path.is_dir() {
+            for item in fs::read_dir(&path).map_err(|e| MemlError::io(&path, e))? {
+                let item = item.map_err(|e| MemlError::io(&path, e))?.path();
+                let ext = item.extension();
+                if ext.is_some() && ext.unwrap() == "meml" {
+                    file_paths.push(item);
                 }
-            } else {
-                panic!("Section `{}`: No input specified. Please add one or more of either `file` or `directory` as a property.", section.name);
+            }
+        } else {
+            return Err(invalid(format!(
+                "Directory `{}` not found.",
+                path.display()
+            )));
+        }
+    }
+
+    file_paths.append(&mut files.iter().map(|item| root_dir.join(item)).collect());
+
+    for path in file_paths {
+        let errors = diagnostics.error_count();
+        let elements =
+            parse_file_with_diagnostics(&path, manifest.exports(), Limits::default(), diagnostics)
+                .into_elements();
+
+        // Keep the previous output of files that could not be evaluated
+        if diagnostics.error_count() > errors {
+            continue;
+        }
+
+        if let Some(serializer) = &serializer {
+            let target_path = action.target_path(&section, &path);
+            if let Err(error) = write_output(serializer.as_ref(), &elements, &path, &target_path) {
+                diagnostics.error(error);
             }
         }
     }
+
+    Ok(())
+}
+
+/// Writes the output for `elements` to `target_path`, unless the file already
+/// has the same content.
+///
+/// The output is serialized twice if it changed, once to compare it and once
+/// to write it, so neither the new nor the old content is kept in memory.
+fn write_output(
+    serializer: &dyn Serializer,
+    elements: &[Element],
+    path: &Path,
+    target_path: &Path,
+) -> Result<(), MemlError> {
+    let mut output = HashingWriter::new(io::sink());
+    serializer
+        .serialize(elements, &mut output)
+        .map_err(|error| error.with_path(path))?;
+
+    if target_path.is_file() {
+        let mut existing = HashingWriter::new(io::sink());
+        File::open(target_path)
+            .and_then(|mut file| io::copy(&mut file, &mut existing))
+            .map_err(|error| MemlError::io(target_path, error))?;
+
+        if existing.digest() == output.digest() {
+            return Ok(());
+        }
+    }
+
+    if let Some(target_dir) = target_path.parent() {
+        fs::create_dir_all(target_dir).map_err(|error| MemlError::io(target_dir, error))?;
+    }
+
+    let file = File::create(target_path).map_err(|error| MemlError::io(target_path, error))?;
+    let mut writer = BufWriter::new(file);
+    serializer
+        .serialize(elements, &mut writer)
+        .map_err(|error| error.with_path(target_path))?;
+    writer
+        .flush()
+        .map_err(|error| MemlError::io(target_path, error))
 }
 
 #[cfg(test)]
@@ -0,0 +1,155 @@
+/*
+meml – XML replacement written in Rust with the pest library <https://pest.rs>.
+Developed to be used in ygo_destiny <https://github.com/myuujiku/ygo_destiny/>.
+Copyright (C) 2022  myujiku
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published
+by the Free Software Foundation, either version 3 of the License,
+or (at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use super::*;
+
+/// The condition of an `if` child or a conditional attribute.
+///
+/// Operands are strings, `$(constant)` or `${argument}`. An operand on its
+/// own is true if it is not empty.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Condition {
+    /// `a || b`
+    Any(Vec<Self>),
+    /// `a && b`
+    All(Vec<Self>),
+    /// `!a`
+    Not(Box<Self>),
+    /// `a == b`
+    Equal(StringTemplate, StringTemplate),
+    /// `a != b`
+    NotEqual(StringTemplate, StringTemplate),
+    /// `a`
+    NotEmpty(StringTemplate),
+}
+
+impl Condition {
+    pub fn compile(pair: Pair<Rule>) -> Result<Self, MemlError> {
+        Ok(match pair.as_rule() {
+            Rule::condition => Self::combine(pair.into_inner(), Self::Any)?,
+            Rule::conjunction => Self::combine(pair.into_inner(), Self::All)?,
+            Rule::negation => {
+                Self::Not(Box::new(Self::compile(pair.into_inner().next().unwrap())?))
+            }
+            Rule::comparison => {
+                let mut inner_rules = pair.into_inner();
+                let left = Self::operand(inner_rules.next().unwrap())?;
+                let operator = inner_rules.next().unwrap().as_rule();
+                let right = Self::operand(inner_rules.next().unwrap())?;
+
+                match operator {
+                    Rule::cmp_eq => Self::Equal(left, right),
+                    Rule::cmp_ne => Self::NotEqual(left, right),
+                    _ => unreachable!(),
+                }
+            }
+            Rule::operand => Self::NotEmpty(Self::operand(pair)?),
+            _ => unreachable!(),
+        })
+    }
+
+    /// Avoids nesting single conditions in `Any` and `All`.
+    fn combine(pairs: Pairs<Rule>, variant: fn(Vec<Self>) -> Self) -> Result<Self, MemlError> {
+        let mut conditions = pairs
+            .map(Self::compile)
+            .collect::<Result<Vec<Self>, MemlError>>()?;
+
+        Ok(if conditions.len() == 1 {
+            conditions.pop().unwrap()
+        } else {
+            variant(conditions)
+        })
+    }
+
+    fn operand(pair: Pair<Rule>) -> Result<StringTemplate, MemlError> {
+        let inner = pair.into_inner().next().unwrap();
+
+        match inner.as_rule() {
+            Rule::string => StringTemplate::compile(inner),
+            _ => Ok(StringTemplate {
+                parts: vec![StringPart::compile(inner)?],
+            }),
+        }
+    }
+
+    pub fn set_path(&mut self, path: &Path) {
+        match self {
+            Self::Any(conditions) | Self::All(conditions) => {
+                for condition in conditions {
+                    condition.set_path(path);
+                }
+            }
+            Self::Not(condition) => condition.set_path(path),
+            Self::Equal(left, right) | Self::NotEqual(left, right) => {
+                left.set_path(path);
+                right.set_path(path);
+            }
+            Self::NotEmpty(operand) => operand.set_path(path),
+        }
+    }
+
+    /// Evaluates the condition. `&&` and `||` stop at the first operand that
+    /// decides the result, so errors in the remaining ones are not reported.
+    pub fn eval(
+        &self,
+        local_definitions: &DefinitionMap,
+        function_arguments: Option<&Arguments>,
+        expansion: &mut Expansion,
+        diagnostics: &mut Diagnostics,
+    ) -> bool {
+        let mut string = |template| {
+            parse_string(
+                template,
+                local_definitions,
+                function_arguments,
+                expansion,
+                diagnostics,
+            )
+        };
+
+        match self {
+            Self::Any(conditions) => conditions.iter().any(|condition| {
+                condition.eval(
+                    local_definitions,
+                    function_arguments,
+                    expansion,
+                    diagnostics,
+                )
+            }),
+            Self::All(conditions) => conditions.iter().all(|condition| {
+                condition.eval(
+                    local_definitions,
+                    function_arguments,
+                    expansion,
+                    diagnostics,
+                )
+            }),
+            Self::Not(condition) => !condition.eval(
+                local_definitions,
+                function_arguments,
+                expansion,
+                diagnostics,
+            ),
+            Self::Equal(left, right) => string(left) == string(right),
+            Self::NotEqual(left, right) => string(left) != string(right),
+            Self::NotEmpty(operand) => !string(operand).is_empty(),
+        }
+    }
+}
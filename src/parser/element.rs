@@ -19,160 +19,618 @@ along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
 use super::*;
 
-#[derive(Clone, Debug)]
-pub struct ElementFactory<'a> {
-    pub pair: Pair<'a, Rule>,
+/// A child of an evaluated element.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Node {
+    Element(Element),
+    /// Text that is escaped when written to XML.
+    Text(String),
+    /// Trusted markup (`!"..."`) that is written to XML without escaping.
+    Raw(String),
 }
 
-impl ElementFactory<'_> {
-    pub fn construct_element(&self, local_definitions: &DefinitionMap) -> Element {
-        Element::construct(self.pair.clone(), local_definitions, None)
+impl Node {
+    /// Number of elements and combined size in bytes of the node and all of
+    /// its descendants.
+    pub(crate) fn totals(&self) -> (usize, usize) {
+        match self {
+            Self::Element(element) => element.totals(),
+            Self::Text(text) | Self::Raw(text) => (0, text.len()),
+        }
     }
 }
 
-#[derive(Clone, Debug, Default)]
+/// An evaluated attribute.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Attribute {
+    pub(crate) namespace: String,
+    pub(crate) name: String,
+    pub(crate) value: String,
+}
+
+impl Attribute {
+    /// Namespace prefix of the attribute, empty if it has none.
+    pub fn namespace(&self) -> &str {
+        &self.namespace
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+
+    /// Name of the attribute including its prefix, e.g. `xml:lang`.
+    pub fn qualified_name(&self) -> String {
+        qualify(&self.namespace, &self.name)
+    }
+}
+
+/// An evaluated meml element.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
 pub struct Element {
-    pub namespace: String,
-    pub name: String,
-    pub arguments: Vec<(String, String)>,
-    pub children: Vec<Self>,
-    pub content: String,
+    pub(crate) namespace: String,
+    pub(crate) name: String,
+    pub(crate) arguments: Vec<Attribute>,
+    /// Child elements and text in the order they were written.
+    pub(crate) nodes: Vec<Node>,
+    /// Namespace prefixes and URIs declared on the element, empty unless it
+    /// is a top-level element.
+    pub(crate) namespaces: Vec<(String, String)>,
 }
 
 impl Element {
-    pub fn factory(pair: Pair<Rule>) -> ElementFactory {
-        ElementFactory { pair: pair }
+    /// Namespace prefix of the element, empty if it has none.
+    pub fn namespace(&self) -> &str {
+        &self.namespace
     }
 
-    pub fn function(pair: Pair<Rule>, arg_names: Vec<String>) -> Function {
-        Function {
-            pair: pair,
-            arg_names: arg_names,
-        }
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Name of the element including its prefix, e.g. `ui:button`.
+    pub fn qualified_name(&self) -> String {
+        qualify(&self.namespace, &self.name)
+    }
+
+    /// Attributes in the order they were written.
+    pub fn attributes(&self) -> &[Attribute] {
+        &self.arguments
+    }
+
+    /// Value of the first attribute called `name`, which includes the prefix
+    /// for namespaced attributes (`xml:lang`).
+    pub fn attribute(&self, name: &str) -> Option<&str> {
+        let (namespace, name) = name.split_once(':').unwrap_or(("", name));
+
+        self.arguments
+            .iter()
+            .find(|attribute| attribute.namespace == namespace && attribute.name == name)
+            .map(|attribute| attribute.value.as_str())
+    }
+
+    /// Namespace declarations (`xmlns`) as prefix and URI, the default
+    /// namespace has an empty prefix.
+    pub fn namespaces(&self) -> &[(String, String)] {
+        &self.namespaces
+    }
+
+    /// Child elements and text in the order they were written.
+    pub fn nodes(&self) -> &[Node] {
+        &self.nodes
     }
 
-    pub fn construct(
-        pair: Pair<Rule>,
+    /// Child elements, skipping text.
+    pub fn children(&self) -> impl Iterator<Item = &Element> {
+        self.nodes.iter().filter_map(|node| match node {
+            Node::Element(element) => Some(element),
+            _ => None,
+        })
+    }
+
+    /// Text of the element without the text of its children, empty if it
+    /// has none.
+    pub fn content(&self) -> String {
+        self.nodes
+            .iter()
+            .filter_map(|node| match node {
+                Node::Text(text) | Node::Raw(text) => Some(text.as_str()),
+                Node::Element(_) => None,
+            })
+            .collect()
+    }
+
+    pub(crate) fn construct(
+        template: &ElementTemplate,
         local_definitions: &DefinitionMap,
         function_arguments: Option<&Arguments>,
+        expansion: &mut Expansion,
+        diagnostics: &mut Diagnostics,
     ) -> Self {
-        let mut inner_rules = pair.into_inner();
-        let mut element = Self::default();
+        let mut element = Self {
+            namespace: template.namespace.to_string(),
+            name: template.name.to_string(),
+            ..Default::default()
+        };
 
-        element.namespace = inner_rules.next().unwrap().as_str().to_string();
-        element.name = inner_rules.next().unwrap().as_str().to_string();
+        check_namespace(
+            &template.namespace,
+            local_definitions,
+            &template.location,
+            diagnostics,
+        );
 
-        element.eval_contents(inner_rules, local_definitions, function_arguments);
-        return element;
+        if expansion.open(&template.location, diagnostics) {
+            element.eval_contents(
+                template,
+                local_definitions,
+                function_arguments,
+                expansion,
+                diagnostics,
+            );
+            expansion.close(element.size(), &template.location, diagnostics);
+        }
+        element
+    }
+
+    /// Size of the name, attributes and text of the element in bytes, not
+    /// including its child elements.
+    fn size(&self) -> usize {
+        self.namespace.len()
+            + self.name.len()
+            + self
+                .arguments
+                .iter()
+                .map(|attribute| {
+                    attribute.namespace.len() + attribute.name.len() + attribute.value.len()
+                })
+                .sum::<usize>()
+            + self
+                .nodes
+                .iter()
+                .map(|node| match node {
+                    Node::Text(text) | Node::Raw(text) => text.len(),
+                    Node::Element(_) => 0,
+                })
+                .sum::<usize>()
     }
 
-    pub fn eval_contents(
+    /// Number of elements and combined size in bytes of the element and all
+    /// of its descendants.
+    pub(crate) fn totals(&self) -> (usize, usize) {
+        self.children().map(Element::totals).fold(
+            (1, self.size()),
+            |(elements, bytes), (child_elements, child_bytes)| {
+                (elements + child_elements, bytes + child_bytes)
+            },
+        )
+    }
+
+    pub(crate) fn eval_contents(
         &mut self,
-        mut pairs: Pairs<Rule>,
+        template: &ElementTemplate,
         local_definitions: &DefinitionMap,
         function_arguments: Option<&Arguments>,
+        expansion: &mut Expansion,
+        diagnostics: &mut Diagnostics,
     ) {
-        for attribute in pairs.next().unwrap().into_inner() {
-            let mut inner_rules = attribute.into_inner();
-            self.arguments.push((
-                inner_rules.next().unwrap().as_str().to_string(),
-                parse_string(
-                    inner_rules.next().unwrap(),
+        for attribute in &template.attributes {
+            if let Some(condition) = &attribute.condition {
+                if !condition.eval(
                     local_definitions,
                     function_arguments,
-                ),
-            ));
-        }
+                    expansion,
+                    diagnostics,
+                ) {
+                    continue;
+                }
+            }
+
+            check_namespace(
+                &attribute.namespace,
+                local_definitions,
+                &attribute.location,
+                diagnostics,
+            );
+            let value = parse_string(
+                &attribute.value,
+                local_definitions,
+                function_arguments,
+                expansion,
+                diagnostics,
+            );
+            check_characters(&value, &attribute.location, diagnostics);
 
-        for child in pairs.next().unwrap().into_inner() {
-            self.eval_child(child, local_definitions, function_arguments);
+            // Checked here so every output format gets the same attributes
+            if !expansion.repeated_attributes
+                && self.arguments.iter().any(|other| {
+                    other.namespace == attribute.namespace && other.name == attribute.name
+                })
+            {
+                diagnostics.error(MemlError::DuplicateAttribute {
+                    name: qualify(&attribute.namespace, &attribute.name),
+                    location: attribute.location.clone(),
+                });
+                continue;
+            }
+
+            self.arguments.push(Attribute {
+                namespace: attribute.namespace.to_string(),
+                name: attribute.name.to_string(),
+                value,
+            });
         }
 
-        if let Some(string) = pairs.next().unwrap().into_inner().next() {
-            self.content = parse_string(string, local_definitions, function_arguments);
+        for child in &template.children {
+            self.eval_child(
+                child,
+                local_definitions,
+                function_arguments,
+                expansion,
+                diagnostics,
+            );
         }
     }
 
-    pub fn eval_child(
+    pub(crate) fn eval_child(
         &mut self,
-        child: Pair<Rule>,
+        child: &ChildTemplate,
         local_definitions: &DefinitionMap,
         function_arguments: Option<&Arguments>,
+        expansion: &mut Expansion,
+        diagnostics: &mut Diagnostics,
     ) {
-        match child.as_rule() {
-            Rule::element => self.children.push(Element::construct(
-                child,
+        match child {
+            ChildTemplate::Element(template) => self.nodes.push(Node::Element(Element::construct(
+                template,
                 local_definitions,
                 function_arguments,
-            )),
-            Rule::const_use => {
-                if let Some(value) = local_definitions
-                    .get("elements")
-                    .unwrap()
-                    .get(child.as_str())
+                expansion,
+                diagnostics,
+            ))),
+            ChildTemplate::Text {
+                value,
+                raw,
+                location,
+            } => {
+                let text = parse_string(
+                    value,
+                    local_definitions,
+                    function_arguments,
+                    expansion,
+                    diagnostics,
+                );
+                check_characters(&text, location, diagnostics);
+                self.nodes.push(if *raw {
+                    Node::Raw(text)
+                } else {
+                    Node::Text(text)
+                });
+            }
+            ChildTemplate::Constant(name) => {
+                if let Some(Definition::Element(def, scope)) =
+                    local_definitions.get("elements").unwrap().get(&name.name)
                 {
-                    if let Definition::Element(def) = value {
-                        self.children.push(def.construct_element(local_definitions));
+                    // Element constants are expanded in place, so they see the
+                    // arguments of the function they are used in
+                    if expansion.enter(name.name.to_string(), &name.location, diagnostics) {
+                        let definitions = scope.as_deref().unwrap_or(local_definitions);
+                        let mut element = Element::construct(
+                            def,
+                            definitions,
+                            function_arguments,
+                            expansion,
+                            diagnostics,
+                        );
+                        if scope.is_some() {
+                            declare_namespaces(&mut element, definitions, local_definitions);
+                        }
+                        self.nodes.push(Node::Element(element));
+                        expansion.leave();
                     }
                 } else {
-                    panic!(
-                        "{}",
-                        Error::new_from_span(
-                            ErrorVariant::<()>::CustomError {
-                                message: "undefined element constant".to_string()
-                            },
-                            child.as_span(),
+                    diagnostics.error(MemlError::UndefinedElement {
+                        name: name.name.to_string(),
+                        location: name.location.clone(),
+                    });
+                }
+            }
+            ChildTemplate::Call {
+                name,
+                arguments,
+                body,
+                location,
+            } => {
+                let args = arguments
+                    .iter()
+                    .map(|argument| {
+                        (
+                            argument.name.as_ref(),
+                            parse_string(
+                                &argument.value,
+                                local_definitions,
+                                function_arguments,
+                                expansion,
+                                diagnostics,
+                            ),
                         )
+                    })
+                    .collect();
+                // The body belongs to the caller, so it is evaluated in its scope
+                let mut container = Element::default();
+                for child in body {
+                    container.eval_child(
+                        child,
+                        local_definitions,
+                        function_arguments,
+                        expansion,
+                        diagnostics,
                     );
                 }
+
+                let functions = local_definitions.get("functions").unwrap();
+                if let Some(Definition::Function(def)) = functions.get(&name.name) {
+                    self.nodes.extend(
+                        def.call(
+                            args,
+                            container.nodes,
+                            local_definitions,
+                            location,
+                            expansion,
+                            diagnostics,
+                        )
+                        .map(Node::Element),
+                    );
+                } else {
+                    diagnostics.error(MemlError::UndefinedFunction {
+                        name: name.name.to_string(),
+                        suggestion: suggest(&name.name, functions.keys()),
+                        location: name.location.clone(),
+                    });
+                }
             }
-            Rule::func_use => {
-                let mut inner_rules = child.into_inner();
-                let name = inner_rules.next().unwrap().as_str();
-                let args = inner_rules
-                    .next()
-                    .unwrap()
-                    .into_inner()
-                    .map(|pair| parse_string(pair, local_definitions, None))
-                    .collect();
-                if let Some(value) = local_definitions.get("functions").unwrap().get(name) {
-                    if let Definition::Function(def) = value {
-                        self.children.push(def.call(args, &local_definitions))
+            ChildTemplate::Slot(location) => match function_arguments {
+                Some(arguments) if arguments.in_function => {
+                    // The children were already counted once, but every copy
+                    // adds to the output again
+                    if expansion.copy(&arguments.children, location, diagnostics) {
+                        self.nodes.extend(arguments.children.iter().cloned());
+                    }
+                }
+                _ => diagnostics.error(MemlError::UnexpectedArgument {
+                    name: "children".to_string(),
+                    location: location.clone(),
+                }),
+            },
+            ChildTemplate::For {
+                variable,
+                source,
+                children,
+                ..
+            } => {
+                let values = match source {
+                    LoopSource::Range {
+                        start,
+                        end,
+                        inclusive,
+                        location,
+                    } => {
+                        let mut bound = |template| {
+                            let value = parse_string(
+                                template,
+                                local_definitions,
+                                function_arguments,
+                                expansion,
+                                diagnostics,
+                            );
+                            value.trim().parse::<i64>().map_err(|_| {
+                                diagnostics.error(MemlError::InvalidNumber {
+                                    value,
+                                    location: location.clone(),
+                                })
+                            })
+                        };
+                        let (Ok(start), Ok(end)) = (bound(start), bound(end)) else {
+                            return;
+                        };
+                        let end = if *inclusive {
+                            end.saturating_add(1)
+                        } else {
+                            end
+                        };
+
+                        let count = usize::try_from(end.saturating_sub(start)).unwrap_or(0);
+                        if !expansion.iterate(count, location, diagnostics) {
+                            return;
+                        }
+                        (start..end).map(|i| i.to_string()).collect()
+                    }
+                    LoopSource::List(list) => {
+                        match local_definitions.get("lists").unwrap().get(&list.name) {
+                            Some(Definition::List(values)) => {
+                                if !expansion.iterate(values.len(), &list.location, diagnostics) {
+                                    return;
+                                }
+                                values.clone()
+                            }
+                            _ => {
+                                diagnostics.error(MemlError::UndefinedList {
+                                    name: list.name.to_string(),
+                                    location: list.location.clone(),
+                                });
+                                return;
+                            }
+                        }
+                    }
+                };
+
+                // `name_index`, `name_first` and `name_last` describe the
+                // position of the current value, the latter two are empty
+                // unless true so they can be used as conditions
+                let flag = |value: bool| if value { "true" } else { "" }.to_string();
+                let mut arguments = function_arguments.cloned().unwrap_or_default();
+                for (index, value) in values.iter().enumerate() {
+                    arguments.insert(variable.to_string(), value.to_string());
+                    arguments.insert(format!("{}_index", variable), index.to_string());
+                    arguments.insert(format!("{}_first", variable), flag(index == 0));
+                    arguments.insert(
+                        format!("{}_last", variable),
+                        flag(index + 1 == values.len()),
+                    );
+
+                    for child in children {
+                        self.eval_child(
+                            child,
+                            local_definitions,
+                            Some(&arguments),
+                            expansion,
+                            diagnostics,
+                        );
+                    }
+                }
+            }
+            ChildTemplate::If {
+                branches,
+                otherwise,
+                ..
+            } => {
+                let children = branches
+                    .iter()
+                    .find(|(condition, _)| {
+                        condition.eval(
+                            local_definitions,
+                            function_arguments,
+                            expansion,
+                            diagnostics,
+                        )
+                    })
+                    .map_or(otherwise, |(_, children)| children);
+
+                for child in children {
+                    self.eval_child(
+                        child,
+                        local_definitions,
+                        function_arguments,
+                        expansion,
+                        diagnostics,
+                    );
+                }
+            }
+            ChildTemplate::List {
+                list,
+                item,
+                template,
+            } => {
+                if let Some(Definition::List(values)) =
+                    local_definitions.get("lists").unwrap().get(&list.name)
+                {
+                    let mut arguments = function_arguments.cloned().unwrap_or_default();
+                    for value in values {
+                        arguments.insert(item.to_string(), value.to_string());
+                        self.nodes.push(Node::Element(Element::construct(
+                            template,
+                            local_definitions,
+                            Some(&arguments),
+                            expansion,
+                            diagnostics,
+                        )));
                     }
+                } else {
+                    diagnostics.error(MemlError::UndefinedList {
+                        name: list.name.to_string(),
+                        location: list.location.clone(),
+                    });
                 }
             }
-            _ => unimplemented!(),
         }
     }
+}
 
-    pub fn as_xml(&self) -> String {
-        let name = if self.namespace.is_empty() {
-            self.name.to_string()
-        } else {
-            format!("{}:{}", self.namespace, self.name)
-        };
+/// Reports an error if `prefix` is neither empty nor declared with
+/// `namespace prefix = "uri"`. `xml` is bound to its namespace by definition.
+fn check_namespace(
+    prefix: &str,
+    local_definitions: &DefinitionMap,
+    location: &Location,
+    diagnostics: &mut Diagnostics,
+) {
+    if !matches!(prefix, "" | "xml")
+        && !local_definitions
+            .get("namespaces")
+            .is_some_and(|namespaces| namespaces.contains_key(prefix))
+    {
+        diagnostics.error(MemlError::UndefinedNamespace {
+            prefix: prefix.to_string(),
+            location: location.clone(),
+        });
+    }
+}
 
-        let arguments = self
-            .arguments
-            .iter()
-            .map(|item| format!(" {}=\"{}\"", item.0, item.1))
-            .collect::<Vec<String>>()
-            .join("");
-
-        let contents = if self.children.is_empty() {
-            self.content.to_string()
-        } else {
-            self.children
-                .iter()
-                .map(|child| child.as_xml())
-                .collect::<Vec<String>>()
-                .join("")
-        };
+/// Reports an error for the first character in `text` that XML does not
+/// allow, i.e. control characters other than tabs and line breaks, and the
+/// noncharacters `U+FFFE` and `U+FFFF`. Such characters cannot be escaped.
+fn check_characters(text: &str, location: &Location, diagnostics: &mut Diagnostics) {
+    if let Some(character) = text.chars().find(|character| {
+        matches!(character, '\0'..='\u{8}' | '\u{b}' | '\u{c}' | '\u{e}'..='\u{1f}')
+            || matches!(character, '\u{fffe}' | '\u{ffff}')
+    }) {
+        diagnostics.error(MemlError::InvalidCharacter {
+            character,
+            location: location.clone(),
+        });
+    }
+}
+
+/// Declares the namespaces of `inner` on `element` that differ from those of
+/// `outer`, for an element built in a different scope than the elements
+/// around it. A default namespace that only `outer` has is undeclared with
+/// `xmlns=""`. Namespaces the element already declares are kept.
+pub(crate) fn declare_namespaces(
+    element: &mut Element,
+    inner: &DefinitionMap,
+    outer: &DefinitionMap,
+) {
+    let uri = |definitions: &DefinitionMap, prefix: &str| match definitions
+        .get("namespaces")
+        .and_then(|namespaces| namespaces.get(prefix))
+    {
+        Some(Definition::String(uri)) => Some(uri.to_string()),
+        _ => None,
+    };
 
-        if contents.is_empty() {
-            format!("<{}{}/>", name, arguments)
-        } else {
-            format!("<{name}{}>{}</{name}>", arguments, contents, name = name)
+    let mut declarations = inner
+        .get("namespaces")
+        .into_iter()
+        .flatten()
+        .filter_map(|(prefix, _)| {
+            let inner_uri = uri(inner, prefix)?;
+            (uri(outer, prefix).as_ref() != Some(&inner_uri))
+                .then(|| (prefix.to_string(), inner_uri))
+        })
+        .collect::<Vec<(String, String)>>();
+    if uri(inner, "").is_none() && uri(outer, "").is_some() {
+        declarations.push((String::new(), String::new()));
+    }
+
+    for (prefix, uri) in declarations {
+        if !element
+            .namespaces
+            .iter()
+            .any(|(declared, _)| *declared == prefix)
+        {
+            element.namespaces.push((prefix, uri));
         }
     }
+    element.namespaces.sort_unstable();
+}
+
+fn qualify(prefix: &str, name: &str) -> String {
+    if prefix.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}:{}", prefix, name)
+    }
 }
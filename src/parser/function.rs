@@ -19,25 +19,193 @@ along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
 use super::*;
 
-#[derive(Clone, Debug)]
-pub struct Function<'a> {
-    pub pair: Pair<'a, Rule>,
-    pub arg_names: Vec<String>,
+/// A compiled function definition.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Function {
+    pub(crate) name: String,
+    pub(crate) arg_names: Vec<String>,
+    /// Default values of optional parameters, evaluated at each call.
+    pub(crate) defaults: HashMap<String, StringTemplate>,
+    pub(crate) body: ElementTemplate,
+    pub(crate) location: Location,
+    /// Scope of the file the function was exported from, see [`Scope`].
+    pub(crate) scope: Option<Scope>,
 }
 
-impl Function<'_> {
-    pub fn call(&self, arguments: Vec<String>, local_definitions: &DefinitionMap) -> Element {
-        let args_len = arguments.len();
-        let names_len = self.arg_names.len();
-
-        let args = if args_len < names_len {
-            panic!()
-        } else if args_len > names_len {
-            panic!()
-        } else {
-            self.arg_names.clone().into_iter().zip(arguments).collect()
+impl Function {
+    pub fn compile(pair: Pair<Rule>) -> Result<Self, MemlError> {
+        let mut inner_rules = pair.into_inner();
+        let name = Ident::compile(inner_rules.next().unwrap());
+        let mut arg_names = Vec::new();
+        let mut defaults = HashMap::new();
+
+        for param in inner_rules.next().unwrap().into_inner() {
+            let mut inner_rules = param.into_inner();
+            let arg_name = inner_rules.next().unwrap().as_str().to_string();
+            if let Some(default) = inner_rules.next() {
+                defaults.insert(
+                    arg_name.to_string(),
+                    StringTemplate::compile(default.into_inner().next().unwrap())?,
+                );
+            }
+            arg_names.push(arg_name);
+        }
+
+        Ok(Self {
+            name: name.name,
+            arg_names,
+            defaults,
+            body: ElementTemplate::compile(inner_rules.next().unwrap())?,
+            location: name.location,
+            scope: None,
+        })
+    }
+
+    pub fn set_path(&mut self, path: &Path) {
+        self.location.set_path(path);
+        for default in self.defaults.values_mut() {
+            default.set_path(path);
+        }
+        self.body.set_path(path);
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn arg_names(&self) -> &[String] {
+        &self.arg_names
+    }
+
+    /// The name and parameters as they are written in the definition, e.g.
+    /// `button(label icon="none")`.
+    pub fn signature(&self) -> String {
+        let params = self
+            .arg_names
+            .iter()
+            .map(|arg_name| match self.defaults.get(arg_name) {
+                Some(default) => format!("{}=\"{}\"", arg_name, default),
+                None => arg_name.to_string(),
+            })
+            .collect::<Vec<String>>();
+
+        format!("{}({})", self.name, params.join(" "))
+    }
+
+    /// Evaluates the function body with `children` as the nodes inserted by
+    /// `${children}`, or returns `None` if the arguments do not match the
+    /// parameters.
+    ///
+    /// Positional arguments are assigned in order, named arguments to the
+    /// parameter with the same name. Parameters without an argument get their
+    /// default value.
+    pub(crate) fn call(
+        &self,
+        arguments: Vec<(Option<&Ident>, String)>,
+        mut children: Vec<Node>,
+        local_definitions: &DefinitionMap,
+        location: &Location,
+        expansion: &mut Expansion,
+        diagnostics: &mut Diagnostics,
+    ) -> Option<Element> {
+        let found = arguments.len();
+        let mut values = vec![None; self.arg_names.len()];
+        let mut valid = true;
+
+        for (position, (name, value)) in arguments.into_iter().enumerate() {
+            let index = match name {
+                None => position,
+                Some(name) => match self.arg_names.iter().position(|arg| *arg == name.name) {
+                    Some(index) => index,
+                    None => {
+                        diagnostics.error(MemlError::UnknownArgument {
+                            name: name.name.to_string(),
+                            signature: self.signature(),
+                            suggestion: suggest(&name.name, &self.arg_names),
+                            location: name.location.clone(),
+                            definition: self.location.clone(),
+                        });
+                        valid = false;
+                        continue;
+                    }
+                },
+            };
+
+            // Too many positional arguments are reported below
+            if let Some(slot) = values.get_mut(index) {
+                if slot.is_some() {
+                    diagnostics.error(MemlError::DuplicateArgument {
+                        name: self.arg_names[index].to_string(),
+                        location: name.map_or(location, |name| &name.location).clone(),
+                    });
+                    valid = false;
+                }
+                *slot = Some(value);
+            }
+        }
+
+        if !valid {
+            return None;
+        }
+
+        if found > self.arg_names.len() {
+            diagnostics.error(MemlError::ArityMismatch {
+                name: self.name.to_string(),
+                signature: self.signature(),
+                expected: self.arg_names.len(),
+                found,
+                location: location.clone(),
+                definition: self.location.clone(),
+            });
+            return None;
+        }
+
+        // Exported functions are evaluated in the file they were defined in,
+        // while the children still belong to the caller
+        let scope = self.scope.as_deref().unwrap_or(local_definitions);
+        if self.scope.is_some() {
+            for node in &mut children {
+                if let Node::Element(child) = node {
+                    declare_namespaces(child, local_definitions, scope);
+                }
+            }
+        }
+
+        let mut args = Arguments {
+            children,
+            in_function: true,
+            ..Default::default()
         };
+        for (arg_name, value) in self.arg_names.iter().zip(values) {
+            let value = match (value, self.defaults.get(arg_name)) {
+                (Some(value), _) => value,
+                (None, Some(default)) => parse_string(default, scope, None, expansion, diagnostics),
+                (None, None) => {
+                    diagnostics.error(MemlError::MissingArgument {
+                        name: arg_name.to_string(),
+                        signature: self.signature(),
+                        location: location.clone(),
+                        definition: self.location.clone(),
+                    });
+                    valid = false;
+                    continue;
+                }
+            };
+            args.insert(arg_name.to_string(), value);
+        }
+
+        if !valid || !expansion.enter(format!("{}()", self.name), location, diagnostics) {
+            return None;
+        }
+
+        let mut element =
+            Element::construct(&self.body, scope, Some(&args), expansion, diagnostics);
+        if self.scope.is_some() {
+            declare_namespaces(&mut element, scope, local_definitions);
+        }
+        expansion.leave();
 
-        Element::construct(self.pair.clone(), local_definitions, Some(&args))
+        Some(element)
     }
 }
@@ -0,0 +1,221 @@
+/*
+meml – XML replacement written in Rust with the pest library <https://pest.rs>.
+Developed to be used in ygo_destiny <https://github.com/myuujiku/ygo_destiny/>.
+Copyright (C) 2022  myujiku
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published
+by the Free Software Foundation, either version 3 of the License,
+or (at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+//! JSON output, see [`JsonSerializer`] for how elements are written.
+
+use std::io::{self, Write};
+
+use super::{
+    serializer::{io_error, serialize_to_string, write_qualified, write_repeated},
+    *,
+};
+
+/// Writes elements as a JSON array, indented with two spaces if `pretty`.
+///
+/// Every element is written as an object with the same keys:
+///
+/// ```json
+/// {
+///   "name": "card",
+///   "namespace": "",
+///   "attributes": { "type": "monster", "xml:lang": "en" },
+///   "children": [{ "name": "b", ... }, "text"],
+///   "text": "text"
+/// }
+/// ```
+///
+/// `attributes` uses the qualified attribute names as keys. If a name is
+/// repeated, which only manifest sections allow, the first value is used.
+/// `children` contains the child elements and text in order, text as
+/// strings. `text` is the text of the element without the text of its
+/// children.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct JsonSerializer {
+    pub pretty: bool,
+}
+
+impl Serializer for JsonSerializer {
+    fn serialize(&self, elements: &[Element], writer: &mut dyn Write) -> Result<(), MemlError> {
+        write_list(
+            writer,
+            "[]",
+            elements,
+            self.pretty,
+            0,
+            |writer, element, depth| write_element(writer, element, self.pretty, depth),
+        )
+        .map_err(io_error)
+    }
+}
+
+impl Element {
+    /// Writes the element as a JSON object, indented with two spaces if
+    /// `pretty`. See [`JsonSerializer`] for its keys.
+    pub fn as_json(&self, pretty: bool) -> String {
+        let mut output = Vec::new();
+        // Writing to a `Vec` cannot fail
+        write_element(&mut output, self, pretty, 0).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+}
+
+/// Writes `elements` as a JSON array, indented with two spaces if `pretty`.
+/// See [`JsonSerializer`] for how elements are written.
+pub fn to_json(elements: &[Element], pretty: bool) -> String {
+    serialize_to_string(&JsonSerializer { pretty }, elements).unwrap()
+}
+
+fn write_element(
+    writer: &mut dyn Write,
+    element: &Element,
+    pretty: bool,
+    depth: usize,
+) -> io::Result<()> {
+    let mut attributes = Vec::<&Attribute>::new();
+    for attribute in element.attributes() {
+        if !attributes.iter().any(|other| {
+            other.namespace() == attribute.namespace() && other.name() == attribute.name()
+        }) {
+            attributes.push(attribute);
+        }
+    }
+
+    writer.write_all(b"{")?;
+    write_key(writer, "name", true, pretty, depth + 1)?;
+    write_string(writer, element.name())?;
+    write_key(writer, "namespace", false, pretty, depth + 1)?;
+    write_string(writer, element.namespace())?;
+    write_key(writer, "attributes", false, pretty, depth + 1)?;
+    write_list(
+        writer,
+        "{}",
+        &attributes,
+        pretty,
+        depth + 1,
+        |writer, attribute, _| {
+            writer.write_all(b"\"")?;
+            write_qualified(writer, attribute.namespace(), attribute.name())?;
+            writer.write_all(if pretty { b"\": " } else { b"\":" })?;
+            write_string(writer, attribute.value())
+        },
+    )?;
+    write_key(writer, "children", false, pretty, depth + 1)?;
+    write_list(
+        writer,
+        "[]",
+        element.nodes(),
+        pretty,
+        depth + 1,
+        |writer, node, depth| match node {
+            Node::Element(child) => write_element(writer, child, pretty, depth),
+            Node::Text(text) | Node::Raw(text) => write_string(writer, text),
+        },
+    )?;
+    write_key(writer, "text", false, pretty, depth + 1)?;
+    writer.write_all(b"\"")?;
+    for node in element.nodes() {
+        if let Node::Text(text) | Node::Raw(text) = node {
+            write_string_contents(writer, text)?;
+        }
+    }
+    writer.write_all(b"\"")?;
+    newline(writer, pretty, depth)?;
+    writer.write_all(b"}")
+}
+
+fn write_key(
+    writer: &mut dyn Write,
+    key: &str,
+    first: bool,
+    pretty: bool,
+    depth: usize,
+) -> io::Result<()> {
+    if !first {
+        writer.write_all(b",")?;
+    }
+    newline(writer, pretty, depth)?;
+    write_string(writer, key)?;
+    writer.write_all(if pretty { b": " } else { b":" })
+}
+
+/// Writes `items` between the two characters of `brackets`, one item per line
+/// if `pretty`. Empty lists are written without a line break.
+fn write_list<T>(
+    writer: &mut dyn Write,
+    brackets: &str,
+    items: &[T],
+    pretty: bool,
+    depth: usize,
+    mut write_item: impl FnMut(&mut dyn Write, &T, usize) -> io::Result<()>,
+) -> io::Result<()> {
+    let (open, close) = brackets.split_at(1);
+    writer.write_all(open.as_bytes())?;
+
+    for (index, item) in items.iter().enumerate() {
+        if index > 0 {
+            writer.write_all(b",")?;
+        }
+        newline(writer, pretty, depth + 1)?;
+        write_item(writer, item, depth + 1)?;
+    }
+
+    if !items.is_empty() {
+        newline(writer, pretty, depth)?;
+    }
+    writer.write_all(close.as_bytes())
+}
+
+fn newline(writer: &mut dyn Write, pretty: bool, depth: usize) -> io::Result<()> {
+    if pretty {
+        writer.write_all(b"\n")?;
+        write_repeated(writer, "  ", depth)?;
+    }
+    Ok(())
+}
+
+/// Writes `value` as a double-quoted string. The escapes are also valid in
+/// YAML and TOML.
+pub(crate) fn write_string(writer: &mut dyn Write, value: &str) -> io::Result<()> {
+    writer.write_all(b"\"")?;
+    write_string_contents(writer, value)?;
+    writer.write_all(b"\"")
+}
+
+fn write_string_contents(writer: &mut dyn Write, value: &str) -> io::Result<()> {
+    let mut start = 0;
+    for (index, character) in value.char_indices() {
+        let replacement = match character {
+            '"' => Some("\\\""),
+            '\\' => Some("\\\\"),
+            '\n' => Some("\\n"),
+            '\r' => Some("\\r"),
+            '\t' => Some("\\t"),
+            c if c < ' ' => None,
+            _ => continue,
+        };
+
+        writer.write_all(&value.as_bytes()[start..index])?;
+        match replacement {
+            Some(replacement) => writer.write_all(replacement.as_bytes())?,
+            None => write!(writer, "\\u{:04x}", character as u32)?,
+        }
+        start = index + character.len_utf8();
+    }
+    writer.write_all(&value.as_bytes()[start..])
+}
@@ -0,0 +1,248 @@
+/*
+meml – XML replacement written in Rust with the pest library <https://pest.rs>.
+Developed to be used in ygo_destiny <https://github.com/myuujiku/ygo_destiny/>.
+Copyright (C) 2022  myujiku
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published
+by the Free Software Foundation, either version 3 of the License,
+or (at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use super::*;
+
+/// Upper bounds for the evaluation of a single document, so templates that
+/// nest too deeply or grow exponentially fail instead of exhausting memory.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Limits {
+    /// Maximum nesting depth of elements.
+    pub max_depth: usize,
+    /// Maximum number of elements.
+    pub max_elements: usize,
+    /// Maximum combined size of element names, attributes and text in bytes.
+    pub max_output_bytes: usize,
+    /// Maximum number of loop iterations, counting every iteration of nested
+    /// loops.
+    pub max_iterations: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_depth: 256,
+            max_elements: 1_000_000,
+            max_output_bytes: 64 * 1024 * 1024,
+            max_iterations: 10_000_000,
+        }
+    }
+}
+
+/// Keeps track of the expansion of a document to enforce its [`Limits`] and
+/// detect constants and functions that use themselves.
+#[derive(Debug)]
+pub(crate) struct Expansion {
+    limits: Limits,
+    /// Element constants (`name`) and functions (`name()`) being expanded.
+    stack: Vec<String>,
+    depth: usize,
+    elements: usize,
+    bytes: usize,
+    iterations: usize,
+    /// Set once a limit is exceeded, after which nothing is expanded anymore.
+    exceeded: bool,
+    /// Whether an element can have several attributes with the same name,
+    /// which manifests use for properties like `file`.
+    pub(crate) repeated_attributes: bool,
+}
+
+impl Expansion {
+    pub fn new(limits: Limits) -> Self {
+        Self {
+            limits,
+            stack: Vec::new(),
+            depth: 0,
+            elements: 0,
+            bytes: 0,
+            iterations: 0,
+            exceeded: false,
+            repeated_attributes: false,
+        }
+    }
+
+    /// Starts expanding the element constant or function `name`, or returns
+    /// `false` if it is already being expanded.
+    pub fn enter(
+        &mut self,
+        name: String,
+        location: &Location,
+        diagnostics: &mut Diagnostics,
+    ) -> bool {
+        if let Some(start) = self.stack.iter().position(|item| *item == name) {
+            let mut path = self.stack[start..].to_vec();
+            path.push(name);
+            diagnostics.error(MemlError::RecursionCycle {
+                path,
+                location: location.clone(),
+            });
+            return false;
+        }
+
+        self.stack.push(name);
+        true
+    }
+
+    pub fn leave(&mut self) {
+        self.stack.pop();
+    }
+
+    /// Starts constructing an element, or returns `false` if that would
+    /// exceed the depth or element limit.
+    pub fn open(&mut self, location: &Location, diagnostics: &mut Diagnostics) -> bool {
+        if self.exceeded {
+            return false;
+        }
+
+        self.depth += 1;
+        self.elements += 1;
+
+        if self.depth > self.limits.max_depth {
+            self.exceed(
+                "nesting depth",
+                self.limits.max_depth,
+                location,
+                diagnostics,
+            );
+        } else if self.elements > self.limits.max_elements {
+            self.exceed(
+                "number of elements",
+                self.limits.max_elements,
+                location,
+                diagnostics,
+            );
+        }
+
+        if self.exceeded {
+            self.depth -= 1;
+        }
+        !self.exceeded
+    }
+
+    /// Finishes constructing an element of `bytes` bytes.
+    pub fn close(&mut self, bytes: usize, location: &Location, diagnostics: &mut Diagnostics) {
+        self.depth -= 1;
+        self.bytes += bytes;
+
+        if !self.exceeded && self.bytes > self.limits.max_output_bytes {
+            self.exceed(
+                "output size in bytes",
+                self.limits.max_output_bytes,
+                location,
+                diagnostics,
+            );
+        }
+    }
+
+    /// Checks that a string of `length` bytes stays within the size limit, or
+    /// returns `false` if it does not or a limit was already exceeded.
+    pub fn grow(
+        &mut self,
+        length: usize,
+        location: &Location,
+        diagnostics: &mut Diagnostics,
+    ) -> bool {
+        if !self.exceeded && length > self.limits.max_output_bytes {
+            self.exceed(
+                "output size in bytes",
+                self.limits.max_output_bytes,
+                location,
+                diagnostics,
+            );
+        }
+        !self.exceeded
+    }
+
+    /// Adds the `count` iterations of a loop, or returns `false` if they
+    /// would exceed the iteration limit. The whole loop is checked before its
+    /// first iteration, so huge ranges are not iterated at all, and nested
+    /// loops add up even if their bodies add no elements.
+    pub fn iterate(
+        &mut self,
+        count: usize,
+        location: &Location,
+        diagnostics: &mut Diagnostics,
+    ) -> bool {
+        if self.exceeded {
+            return false;
+        }
+
+        self.iterations = self.iterations.saturating_add(count);
+        if self.iterations > self.limits.max_iterations {
+            self.exceed(
+                "number of loop iterations",
+                self.limits.max_iterations,
+                location,
+                diagnostics,
+            );
+        }
+        !self.exceeded
+    }
+
+    /// Adds copies of already constructed nodes, or returns `false` if that
+    /// would exceed the element or size limit.
+    pub fn copy(
+        &mut self,
+        nodes: &[Node],
+        location: &Location,
+        diagnostics: &mut Diagnostics,
+    ) -> bool {
+        if self.exceeded {
+            return false;
+        }
+
+        for (count, bytes) in nodes.iter().map(Node::totals) {
+            self.elements += count;
+            self.bytes += bytes;
+        }
+
+        if self.elements > self.limits.max_elements {
+            self.exceed(
+                "number of elements",
+                self.limits.max_elements,
+                location,
+                diagnostics,
+            );
+        } else if self.bytes > self.limits.max_output_bytes {
+            self.exceed(
+                "output size in bytes",
+                self.limits.max_output_bytes,
+                location,
+                diagnostics,
+            );
+        }
+        !self.exceeded
+    }
+
+    fn exceed(
+        &mut self,
+        limit: &str,
+        max: usize,
+        location: &Location,
+        diagnostics: &mut Diagnostics,
+    ) {
+        self.exceeded = true;
+        diagnostics.error(MemlError::LimitExceeded {
+            limit: limit.to_string(),
+            max,
+            location: location.clone(),
+        });
+    }
+}
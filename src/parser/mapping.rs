@@ -0,0 +1,154 @@
+/*
+meml – XML replacement written in Rust with the pest library <https://pest.rs>.
+Developed to be used in ygo_destiny <https://github.com/myuujiku/ygo_destiny/>.
+Copyright (C) 2022  myujiku
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published
+by the Free Software Foundation, either version 3 of the License,
+or (at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+//! Mapping of elements to nested tables, used by the YAML and TOML output and
+//! described on [`YamlSerializer`].
+
+use std::borrow::Cow;
+
+use super::*;
+
+/// Key for the text of elements that are tables.
+pub(crate) const TEXT_KEY: &str = "$text";
+
+/// Key and value of a table. Both borrow from the elements unless they have
+/// to be put together, e.g. the key of a prefixed name.
+pub(crate) type Entry<'a> = (Cow<'a, str>, Value<'a>);
+
+pub(crate) enum Value<'a> {
+    String(Cow<'a, str>),
+    Array(Vec<Value<'a>>),
+    Table(Vec<Entry<'a>>),
+}
+
+/// Maps `elements` to the entries of a table, see [`YamlSerializer`].
+///
+/// `path` is the dotted path of the table for error messages.
+pub(crate) fn table<'a>(
+    elements: &[&'a Element],
+    format: &str,
+    path: &str,
+) -> Result<Vec<Entry<'a>>, MemlError> {
+    let mut entries = Vec::<(Cow<str>, Vec<Value>)>::new();
+
+    for element in elements {
+        let name = key(element.namespace(), element.name());
+        let value = value(element, format, &join(path, &name))?;
+        match entries.iter_mut().find(|(key, _)| *key == name) {
+            Some((_, values)) => values.push(value),
+            None => entries.push((name, vec![value])),
+        }
+    }
+
+    Ok(entries
+        .into_iter()
+        .map(|(key, mut values)| {
+            let value = if values.len() == 1 {
+                values.pop().unwrap()
+            } else {
+                Value::Array(values)
+            };
+            (key, value)
+        })
+        .collect())
+}
+
+fn value<'a>(element: &'a Element, format: &str, path: &str) -> Result<Value<'a>, MemlError> {
+    let children = element.children().collect::<Vec<&Element>>();
+    if element.attributes().is_empty() && children.is_empty() {
+        return Ok(Value::String(text(element)));
+    }
+
+    let content = text(element);
+    if !content.is_empty() && !children.is_empty() {
+        return Err(MemlError::UnsupportedOutput {
+            path: None,
+            format: format.to_string(),
+            key: path.to_string(),
+            reason: "text mixed with child elements loses its position".to_string(),
+        });
+    }
+
+    let mut entries = Vec::<Entry>::new();
+    for attribute in element.attributes() {
+        let name = key(attribute.namespace(), attribute.name());
+        if !entries.iter().any(|(key, _)| *key == name) {
+            entries.push((name, Value::String(Cow::Borrowed(attribute.value()))));
+        }
+    }
+
+    for (key, value) in table(&children, format, path)? {
+        if entries.iter().any(|(name, _)| *name == key) {
+            return Err(MemlError::UnsupportedOutput {
+                path: None,
+                format: format.to_string(),
+                key: join(path, &key),
+                reason: "an attribute and a child element have the same name".to_string(),
+            });
+        }
+        entries.push((key, value));
+    }
+
+    if !content.is_empty() {
+        entries.push((Cow::Borrowed(TEXT_KEY), Value::String(content)));
+    }
+
+    Ok(Value::Table(entries))
+}
+
+/// Qualified name of an element or attribute, only allocated if it has a
+/// prefix.
+fn key<'a>(namespace: &str, name: &'a str) -> Cow<'a, str> {
+    if namespace.is_empty() {
+        Cow::Borrowed(name)
+    } else {
+        Cow::Owned(format!("{}:{}", namespace, name))
+    }
+}
+
+/// Text of `element` like [`Element::content`], only allocated if it is
+/// split into several nodes.
+fn text(element: &Element) -> Cow<'_, str> {
+    let mut texts = element.nodes().iter().filter_map(|node| match node {
+        Node::Text(text) | Node::Raw(text) => Some(text.as_str()),
+        Node::Element(_) => None,
+    });
+
+    match (texts.next(), texts.next()) {
+        (None, _) => Cow::Borrowed(""),
+        (Some(text), None) => Cow::Borrowed(text),
+        _ => Cow::Owned(element.content()),
+    }
+}
+
+pub(crate) fn join(path: &str, key: &str) -> String {
+    if path.is_empty() {
+        key.to_string()
+    } else {
+        format!("{}.{}", path, key)
+    }
+}
+
+/// Whether `key` can be written without quotes in YAML and TOML.
+pub(crate) fn is_bare_key(key: &str) -> bool {
+    !key.is_empty()
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
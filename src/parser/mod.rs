@@ -17,208 +17,519 @@ You should have received a copy of the GNU General Public License
 along with this program.  If not, see <https://www.gnu.org/licenses/>.
 */
 
+mod condition;
 mod element;
 mod function;
+mod json;
+mod limits;
+mod mapping;
+mod serializer;
 mod string;
+mod template;
+mod toml;
+mod xml;
+mod yaml;
 
-use std::collections::HashMap;
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    path::Path,
+    sync::Arc,
+};
 
 use once_cell::sync::OnceCell;
 
 use pest::{
-    error::{Error, ErrorVariant},
     iterators::{Pair, Pairs},
     Parser,
 };
 
-use element::{Element, ElementFactory};
-use function::Function;
+use crate::{
+    diagnostic::Diagnostics,
+    error::{suggest, Location, MemlError},
+};
+
+pub use condition::Condition;
+use element::declare_namespaces;
+pub use element::{Attribute, Element, Node};
+pub use function::Function;
+pub use json::{to_json, JsonSerializer};
+pub(crate) use limits::Expansion;
+pub use limits::Limits;
+pub use serializer::{HashingWriter, Serializer};
 use string::parse_string;
+use template::{validate_name, Ident, LoopSource, StringPart};
+pub use template::{ChildTemplate, ElementTemplate, StringTemplate};
+pub use toml::{to_toml, TomlSerializer};
+pub use xml::{to_xml, Indent, LineEnding, XmlOptions, XmlSerializer};
+pub use yaml::{to_yaml, YamlSerializer};
 
 #[derive(Parser)]
 #[grammar = "meml.pest"]
 pub struct MemlParser {}
 
-#[derive(Clone, Debug)]
-pub enum Definition<'a> {
+/// A compiled definition. Definitions do not borrow from the source they were
+/// defined in, so they can be kept around and reused for other sources.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Definition {
     String(String),
-    Element(ElementFactory<'a>),
-    Function(Function<'a>),
+    /// An element constant and, if it was exported, the scope of the file it
+    /// was defined in.
+    Element(ElementTemplate, Option<Scope>),
+    Function(Function),
+    List(Vec<String>),
+}
+
+impl Definition {
+    /// Sets the file path of every location in the definition that has none.
+    /// The definitions in its scope already have theirs.
+    pub fn set_path(&mut self, path: &Path) {
+        match self {
+            Self::Element(template, _) => template.set_path(path),
+            Self::Function(function) => function.set_path(path),
+            Self::String(_) | Self::List(_) => (),
+        }
+    }
+
+    /// Evaluates the definition in `scope` wherever it is used, unless it
+    /// already has a scope.
+    fn set_scope(&mut self, scope: &Scope) {
+        match self {
+            Self::Element(_, definition_scope)
+            | Self::Function(Function {
+                scope: definition_scope,
+                ..
+            }) => {
+                definition_scope.get_or_insert_with(|| scope.clone());
+            }
+            Self::String(_) | Self::List(_) => (),
+        }
+    }
 }
 
-pub type Arguments = HashMap<String, String>;
-pub type DefinitionMap<'a> = HashMap<String, HashMap<String, Definition<'a>>>;
+/// All definitions of a file, shared by the definitions it exports. Exported
+/// element constants and functions are evaluated in the scope of the file
+/// they were defined in, so they can use its constants, functions, lists and
+/// namespaces wherever they are imported.
+pub type Scope = Arc<DefinitionMap>;
 
-pub fn parse_raw(raw_input: &str) -> Pairs<Rule> {
-    let pairs = MemlParser::parse(Rule::meml, raw_input);
-    match pairs {
-        Ok(x) => x,
-        x => panic!("{}", x.unwrap_err()),
+/// Values available inside a function body.
+#[derive(Clone, Debug, Default)]
+pub struct Arguments {
+    /// Values of the parameters, accessed with `${name}`.
+    pub(crate) strings: HashMap<String, String>,
+    /// Nodes passed in the body of the call, inserted with `${children}`.
+    pub(crate) children: Vec<Node>,
+    /// Whether the values are inside a function body. Loops outside of any
+    /// function only provide their own variables.
+    pub(crate) in_function: bool,
+}
+
+impl Arguments {
+    pub fn get(&self, name: &str) -> Option<&String> {
+        self.strings.get(name)
     }
+
+    pub fn insert(&mut self, name: String, value: String) {
+        self.strings.insert(name, value);
+    }
+}
+
+pub type Definitions = HashMap<String, Definition>;
+pub type DefinitionMap = HashMap<String, Definitions>;
+
+pub fn parse_raw(raw_input: &str) -> Result<Pairs<'_, Rule>, MemlError> {
+    Ok(MemlParser::parse(Rule::meml, raw_input)?)
 }
 
-pub fn get_definitions<'a>(
-    pairs: Pairs<'a, Rule>,
-    external_definitions: &DefinitionMap<'a>,
-) -> (DefinitionMap<'a>, DefinitionMap<'a>, Vec<Pair<'a, Rule>>) {
+pub(crate) fn get_definitions(
+    pairs: Pairs<Rule>,
+    external_definitions: &DefinitionMap,
+    expansion: &mut Expansion,
+    diagnostics: &mut Diagnostics,
+) -> (DefinitionMap, DefinitionMap, Vec<ChildTemplate>) {
     let mut local_definitions = HashMap::from([
         ("strings".to_string(), HashMap::new()),
         ("elements".to_string(), HashMap::new()),
         ("functions".to_string(), HashMap::new()),
+        ("lists".to_string(), HashMap::new()),
     ]);
     let mut exports = local_definitions.clone();
-    let mut remaining = Vec::<Pair<Rule>>::new();
+    // Namespace declarations cannot be exported, exported definitions keep
+    // them through their scope
+    local_definitions.insert("namespaces".to_string(), HashMap::new());
+    let mut remaining = Vec::new();
+    // Categories and names of the definitions included with `use`
+    let mut imported = HashSet::new();
 
     for pair in pairs {
-        let (strings, elements, functions) = eval_definition(
+        match eval_definition(
             pair,
             external_definitions,
             &mut local_definitions,
             &mut exports,
+            &mut imported,
             &mut remaining,
-        );
-        local_definitions
-            .get_mut("strings")
-            .unwrap()
-            .extend(strings);
-        local_definitions
-            .get_mut("elements")
-            .unwrap()
-            .extend(elements);
-        local_definitions
-            .get_mut("functions")
-            .unwrap()
-            .extend(functions);
+            expansion,
+            diagnostics,
+        ) {
+            Ok(definitions) => extend_definitions(&mut local_definitions, definitions),
+            Err(error) => diagnostics.error(error),
+        }
+    }
+
+    // Errors in the scope point to this file wherever it is used
+    let mut scope = local_definitions.clone();
+    if let Some(path) = diagnostics.path() {
+        for definitions in scope.values_mut() {
+            for definition in definitions.values_mut() {
+                definition.set_path(path);
+            }
+        }
+    }
+    let scope = Arc::new(scope);
+    for definitions in exports.values_mut() {
+        for definition in definitions.values_mut() {
+            definition.set_scope(&scope);
+        }
+    }
+
+    (local_definitions, exports, remaining)
+}
+
+fn extend_definitions(definitions: &mut DefinitionMap, other: DefinitionMap) {
+    for (category, values) in other {
+        definitions.entry(category).or_default().extend(values);
+    }
+}
+
+/// Adds a local definition to `definitions`, warning if it replaces one that
+/// is already defined. Replacing a definition included with `use` is an
+/// error, like including one with the name of a local definition.
+fn define(
+    definitions: &mut DefinitionMap,
+    local_definitions: &DefinitionMap,
+    imported: &HashSet<(String, String)>,
+    category: &str,
+    name: Ident,
+    value: Definition,
+    diagnostics: &mut Diagnostics,
+) -> Result<(), MemlError> {
+    if imported.contains(&(category.to_string(), name.name.to_string())) {
+        return Err(MemlError::DuplicateDefinition {
+            def_type: category.trim_end_matches('s').to_string(),
+            name: name.name,
+            location: name.location,
+        });
+    } else if local_definitions
+        .get(category)
+        .is_some_and(|values| values.contains_key(&name.name))
+    {
+        diagnostics.warning(MemlError::Redefinition {
+            def_type: category.trim_end_matches('s').to_string(),
+            name: name.name.to_string(),
+            location: name.location,
+        });
     }
 
-    return (local_definitions, exports, remaining);
+    definitions.insert(category.to_string(), HashMap::from([(name.name, value)]));
+    Ok(())
 }
 
-fn eval_definition<'a>(
-    pair: Pair<'a, Rule>,
-    external_definitions: &DefinitionMap<'a>,
-    local_definitions: &mut DefinitionMap<'a>,
-    exports: &mut DefinitionMap<'a>,
-    remaining: &mut Vec<Pair<'a, Rule>>,
-) -> (
-    HashMap<String, Definition<'a>>,
-    HashMap<String, Definition<'a>>,
-    HashMap<String, Definition<'a>>,
-) {
-    let mut strings = HashMap::new();
-    let mut elements = HashMap::new();
-    let mut functions = HashMap::new();
+// Every part of the state of `get_definitions` is passed explicitly
+#[allow(clippy::too_many_arguments)]
+fn eval_definition(
+    pair: Pair<Rule>,
+    external_definitions: &DefinitionMap,
+    local_definitions: &mut DefinitionMap,
+    exports: &mut DefinitionMap,
+    imported: &mut HashSet<(String, String)>,
+    remaining: &mut Vec<ChildTemplate>,
+    expansion: &mut Expansion,
+    diagnostics: &mut Diagnostics,
+) -> Result<DefinitionMap, MemlError> {
+    let mut definitions = DefinitionMap::new();
 
     match pair.as_rule() {
         Rule::string_const_def => {
             let mut inner_rules = pair.into_inner();
-            let key = inner_rules.next().unwrap().as_str().to_string();
+            let name = Ident::compile(inner_rules.next().unwrap());
             let val = Definition::String(parse_string(
-                inner_rules.next().unwrap(),
-                &local_definitions,
+                &StringTemplate::compile(inner_rules.next().unwrap())?,
+                local_definitions,
                 None,
+                expansion,
+                diagnostics,
             ));
-            strings.insert(key, val);
+            define(
+                &mut definitions,
+                local_definitions,
+                imported,
+                "strings",
+                name,
+                val,
+                diagnostics,
+            )?;
         }
         Rule::element_const_def => {
             let mut inner_rules = pair.into_inner();
-            elements.insert(
-                inner_rules.next().unwrap().as_str().to_string(),
-                Definition::Element(Element::factory(inner_rules.next().unwrap())),
-            );
+            let name = Ident::compile(inner_rules.next().unwrap());
+            let val =
+                Definition::Element(ElementTemplate::compile(inner_rules.next().unwrap())?, None);
+            define(
+                &mut definitions,
+                local_definitions,
+                imported,
+                "elements",
+                name,
+                val,
+                diagnostics,
+            )?;
         }
         Rule::func_def => {
+            let function = Function::compile(pair)?;
+            let name = Ident {
+                name: function.name.to_string(),
+                location: function.location.clone(),
+            };
+            define(
+                &mut definitions,
+                local_definitions,
+                imported,
+                "functions",
+                name,
+                Definition::Function(function),
+                diagnostics,
+            )?;
+        }
+        Rule::list_def => {
             let mut inner_rules = pair.into_inner();
-            let name = inner_rules.next().unwrap().as_str().to_string();
-            let arg_names = inner_rules
+            let name = Ident::compile(inner_rules.next().unwrap());
+            let items = inner_rules
                 .next()
                 .unwrap()
                 .into_inner()
-                .map(|pair| pair.as_str().to_string())
-                .collect();
-            functions.insert(
+                .map(StringTemplate::compile)
+                .collect::<Result<Vec<StringTemplate>, MemlError>>()?;
+            let val = Definition::List(
+                items
+                    .iter()
+                    .map(|item| parse_string(item, local_definitions, None, expansion, diagnostics))
+                    .collect(),
+            );
+            define(
+                &mut definitions,
+                local_definitions,
+                imported,
+                "lists",
                 name,
-                Definition::Function(Element::function(inner_rules.next().unwrap(), arg_names)),
+                val,
+                diagnostics,
+            )?;
+        }
+        Rule::namespace_def => {
+            let location = Location::from_span(pair.as_span());
+            let mut inner_rules = pair.into_inner();
+            let prefix = inner_rules.next().unwrap();
+            if !prefix.as_str().is_empty() {
+                validate_name("namespace prefix", &prefix)?;
+            }
+            let name = Ident::compile(prefix);
+            let uri = parse_string(
+                &StringTemplate::compile(inner_rules.next().unwrap())?,
+                local_definitions,
+                None,
+                expansion,
+                diagnostics,
             );
+
+            if let Some(reason) = check_namespace_declaration(&name.name, &uri, local_definitions) {
+                return Err(MemlError::InvalidNamespace { reason, location });
+            }
+            let uri = Definition::String(uri);
+            define(
+                &mut definitions,
+                local_definitions,
+                imported,
+                "namespaces",
+                name,
+                uri,
+                diagnostics,
+            )?;
         }
         Rule::export => {
-            let (strings, elements, functions) = eval_definition(
+            definitions = eval_definition(
                 pair.into_inner().next().unwrap(),
                 external_definitions,
                 local_definitions,
                 exports,
+                imported,
                 remaining,
-            );
-            exports.get_mut("strings").unwrap().extend(strings);
-            exports.get_mut("elements").unwrap().extend(elements);
-            exports.get_mut("functions").unwrap().extend(functions);
+                expansion,
+                diagnostics,
+            )?;
+
+            // Exported definitions are also available in the exporting file
+            extend_definitions(exports, definitions.clone());
         }
         Rule::include => {
             let span = pair.as_span();
             let mut inner_rules = pair.into_inner();
             let def_type = inner_rules.next().unwrap().as_str();
             let def_category = format!("{}s", def_type);
-            let def_name = inner_rules.next().unwrap().as_str().to_string();
-            if let Some(value) = external_definitions
+            let name_pair = inner_rules.next().unwrap();
+            let def_name = name_pair.as_str().to_string();
+            let rename = inner_rules.next().unwrap();
+
+            // `use <type> name as alias` makes the definition available as `alias`
+            let (alias, alias_span) = if rename.as_str().is_empty() {
+                (def_name.to_string(), name_pair.as_span())
+            } else {
+                (rename.as_str().to_string(), rename.as_span())
+            };
+
+            if local_definitions
                 .get(&def_category)
-                .unwrap()
-                .get(&def_name)
+                .is_some_and(|category| category.contains_key(&alias))
             {
-                local_definitions
-                    .get_mut(&def_category)
-                    .unwrap()
-                    .insert(def_name, value.clone());
+                return Err(MemlError::DuplicateDefinition {
+                    def_type: def_type.to_string(),
+                    name: alias,
+                    location: Location::from_span(alias_span),
+                });
+            }
+
+            let external = external_definitions.get(&def_category);
+            if let Some(value) = external.and_then(|category| category.get(&def_name)) {
+                let mut value = value.clone();
+                if let Definition::Function(function) = &mut value {
+                    function.name = alias.to_string();
+                }
+                imported.insert((def_category.to_string(), alias.to_string()));
+                definitions.insert(def_category, HashMap::from([(alias, value)]));
             } else {
-                panic!(
-                    "{}",
-                    Error::new_from_span(
-                        ErrorVariant::<()>::CustomError {
-                            message: format!(
-                                "undefined {} constant; available values: {}",
-                                def_type,
-                                external_definitions
-                                    .get(&def_category)
-                                    .unwrap()
-                                    .keys()
-                                    .map(|item| format!("`{}`", item))
-                                    .collect::<Vec<String>>()
-                                    .join(", ")
-                            )
-                        },
-                        span,
-                    )
-                );
+                let mut available = external
+                    .map(|category| category.keys().cloned().collect::<Vec<String>>())
+                    .unwrap_or_default();
+                available.sort_unstable();
+
+                return Err(MemlError::UndefinedImport {
+                    def_type: def_type.to_string(),
+                    name: def_name,
+                    available,
+                    location: Location::from_span(span),
+                });
             }
         }
         Rule::EOI => (),
-        _ => remaining.push(pair),
+        _ => remaining.push(ChildTemplate::compile(pair)?),
     }
 
-    return (strings, elements, functions);
+    Ok(definitions)
 }
 
-pub fn get_contents(pairs: Vec<Pair<Rule>>, local_definitions: DefinitionMap) -> Vec<Element> {
+/// Checks that declaring `prefix` keeps the output namespace-well-formed,
+/// returning the reason if it does not.
+fn check_namespace_declaration(
+    prefix: &str,
+    uri: &str,
+    local_definitions: &DefinitionMap,
+) -> Option<String> {
+    const XML_NAMESPACE: &str = "http://www.w3.org/XML/1998/namespace";
+    const XMLNS_NAMESPACE: &str = "http://www.w3.org/2000/xmlns/";
+
+    let prefix_name = if prefix.is_empty() {
+        "the default namespace".to_string()
+    } else {
+        format!("namespace prefix `{}`", prefix)
+    };
+
+    Some(
+        if local_definitions
+            .get("namespaces")
+            .is_some_and(|namespaces| namespaces.contains_key(prefix))
+        {
+            format!("{} is already declared in this file", prefix_name)
+        } else if prefix == "xmlns" {
+            "the prefix `xmlns` is reserved and cannot be declared".to_string()
+        } else if prefix == "xml" && uri != XML_NAMESPACE {
+            format!("the prefix `xml` can only be bound to `{}`", XML_NAMESPACE)
+        } else if prefix != "xml" && uri == XML_NAMESPACE {
+            format!("`{}` can only be bound to the prefix `xml`", XML_NAMESPACE)
+        } else if uri == XMLNS_NAMESPACE {
+            format!("`{}` is reserved and cannot be declared", XMLNS_NAMESPACE)
+        } else {
+            return None;
+        },
+    )
+}
+
+pub(crate) fn get_contents(
+    templates: Vec<ChildTemplate>,
+    local_definitions: DefinitionMap,
+    expansion: &mut Expansion,
+    diagnostics: &mut Diagnostics,
+) -> Vec<Element> {
     let mut root = Vec::new();
     let mut element_container = OnceCell::with_value(Element::default());
 
-    for pair in pairs {
-        match pair.as_rule() {
-            Rule::element => {
-                root.push(Element::construct(pair, &local_definitions, None));
+    for template in templates {
+        match template {
+            ChildTemplate::Element(template) => {
+                root.push(Element::construct(
+                    &template,
+                    &local_definitions,
+                    None,
+                    expansion,
+                    diagnostics,
+                ));
             }
-            Rule::const_use => {
+            _ => {
                 let elem = element_container.get_mut().unwrap();
-                elem.eval_child(pair, &local_definitions, None);
-                root.append(&mut elem.children);
+                elem.eval_child(&template, &local_definitions, None, expansion, diagnostics);
+                let mut has_text = false;
+                for node in elem.nodes.drain(..) {
+                    match node {
+                        Node::Element(element) => root.push(element),
+                        Node::Text(_) | Node::Raw(_) => has_text = true,
+                    }
+                }
+                if has_text {
+                    diagnostics.error(MemlError::UnexpectedText {
+                        location: template.location().clone(),
+                    });
+                }
             }
-            Rule::func_use => {
-                let elem = element_container.get_mut().unwrap();
-                elem.eval_child(pair, &local_definitions, None);
-                root.append(&mut elem.children);
+        }
+    }
+
+    // Every top-level element declares the namespaces, the default one first
+    let mut namespaces = local_definitions
+        .get("namespaces")
+        .into_iter()
+        .flatten()
+        .filter_map(|(prefix, definition)| match definition {
+            Definition::String(uri) => Some((prefix.to_string(), uri.to_string())),
+            _ => None,
+        })
+        .collect::<Vec<(String, String)>>();
+    namespaces.sort_unstable();
+    for element in &mut root {
+        // Elements from other files may already declare some namespaces
+        let declared = std::mem::replace(&mut element.namespaces, namespaces.clone());
+        for (prefix, uri) in declared {
+            match element
+                .namespaces
+                .iter_mut()
+                .find(|(namespace, _)| *namespace == prefix)
+            {
+                Some(namespace) => namespace.1 = uri,
+                None => element.namespaces.push((prefix, uri)),
             }
-            _ => unreachable!(),
         }
+        element.namespaces.sort_unstable();
     }
 
-    return root;
+    root
 }
@@ -0,0 +1,133 @@
+/*
+meml – XML replacement written in Rust with the pest library <https://pest.rs>.
+Developed to be used in ygo_destiny <https://github.com/myuujiku/ygo_destiny/>.
+Copyright (C) 2022  myujiku
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published
+by the Free Software Foundation, either version 3 of the License,
+or (at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::Hasher,
+    io::{self, Write},
+    path::PathBuf,
+};
+
+use super::*;
+
+/// Writes evaluated elements in an output format.
+pub trait Serializer {
+    /// Writes `elements` to `writer` as they are serialized.
+    ///
+    /// I/O errors are returned as [`MemlError::Io`] with an empty path, see
+    /// [`MemlError::with_path`].
+    fn serialize(&self, elements: &[Element], writer: &mut dyn Write) -> Result<(), MemlError>;
+}
+
+/// Serializes `elements` into a string.
+pub(crate) fn serialize_to_string(
+    serializer: &dyn Serializer,
+    elements: &[Element],
+) -> Result<String, MemlError> {
+    let mut output = Vec::new();
+    serializer.serialize(elements, &mut output)?;
+    // All serializers write valid UTF-8
+    Ok(String::from_utf8(output).unwrap())
+}
+
+pub(crate) fn io_error(error: io::Error) -> MemlError {
+    MemlError::Io {
+        path: PathBuf::new(),
+        error,
+    }
+}
+
+/// Passes everything written on to another writer and keeps a hash of it, so
+/// outputs can be compared without keeping them in memory.
+#[derive(Debug)]
+pub struct HashingWriter<W> {
+    inner: W,
+    hasher: DefaultHasher,
+    length: u64,
+}
+
+impl<W: Write> HashingWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self {
+            inner,
+            hasher: DefaultHasher::new(),
+            length: 0,
+        }
+    }
+
+    /// Hash of everything written so far. Equal outputs have the same hash
+    /// regardless of how they were split into writes.
+    pub fn digest(&self) -> u64 {
+        let mut hasher = self.hasher.clone();
+        hasher.write_u64(self.length);
+        hasher.finish()
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.write(&buf[..written]);
+        self.length += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Writes `text` with every character that `escape` returns a replacement
+/// for replaced.
+pub(crate) fn write_escaped(
+    writer: &mut dyn Write,
+    text: &str,
+    escape: impl Fn(char) -> Option<&'static str>,
+) -> io::Result<()> {
+    let mut start = 0;
+    for (index, character) in text.char_indices() {
+        if let Some(replacement) = escape(character) {
+            writer.write_all(&text.as_bytes()[start..index])?;
+            writer.write_all(replacement.as_bytes())?;
+            start = index + character.len_utf8();
+        }
+    }
+    writer.write_all(&text.as_bytes()[start..])
+}
+
+/// Writes `unit` `count` times.
+pub(crate) fn write_repeated(writer: &mut dyn Write, unit: &str, count: usize) -> io::Result<()> {
+    for _ in 0..count {
+        writer.write_all(unit.as_bytes())?;
+    }
+    Ok(())
+}
+
+/// Writes `prefix:name`, or only `name` if `prefix` is empty.
+pub(crate) fn write_qualified(writer: &mut dyn Write, prefix: &str, name: &str) -> io::Result<()> {
+    if !prefix.is_empty() {
+        writer.write_all(prefix.as_bytes())?;
+        writer.write_all(b":")?;
+    }
+    writer.write_all(name.as_bytes())
+}
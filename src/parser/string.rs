@@ -19,66 +19,66 @@ along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
 use super::*;
 
+/// Substitutes the constants and arguments in `template`.
+///
+/// The string is checked against the size limit as values are appended, so
+/// constants and arguments that repeat each other fail before they use up
+/// memory. The result is incomplete once a limit was exceeded.
 pub fn parse_string(
-    pair: Pair<Rule>,
+    template: &StringTemplate,
     local_definitions: &DefinitionMap,
     function_arguments: Option<&Arguments>,
+    expansion: &mut Expansion,
+    diagnostics: &mut Diagnostics,
 ) -> String {
     let mut result = String::new();
 
-    for string_component in pair.into_inner().next().unwrap().into_inner() {
-        match string_component.as_rule() {
-            Rule::qtext => result.push_str(string_component.as_str()),
-            Rule::sconst => {
-                let pair = string_component.into_inner().next().unwrap();
-                let name = pair.as_str();
-
-                if let Some(value) = local_definitions.get("strings").unwrap().get(name) {
-                    if let Definition::String(def) = value {
-                        result.push_str(def.as_str());
+    for part in &template.parts {
+        match part {
+            StringPart::Text(text) => result.push_str(text),
+            StringPart::Constant(name) => {
+                if let Some(Definition::String(def)) =
+                    local_definitions.get("strings").unwrap().get(&name.name)
+                {
+                    if !expansion.grow(result.len() + def.len(), &name.location, diagnostics) {
+                        break;
                     }
+                    result.push_str(def.as_str());
                 } else {
-                    panic!(
-                        "{}",
-                        Error::new_from_span(
-                            ErrorVariant::<()>::CustomError {
-                                message: "undefined string constant".to_string()
-                            },
-                            pair.as_span(),
-                        )
-                    );
+                    diagnostics.error(MemlError::UndefinedString {
+                        name: name.name.to_string(),
+                        location: name.location.clone(),
+                    });
                 }
             }
-            Rule::sarg => {
+            StringPart::Argument(name) => {
                 if let Some(arguments) = function_arguments {
-                    let pair = string_component.into_inner().next().unwrap();
-                    let name = pair.as_str();
-
-                    if let Some(value) = arguments.get(name) {
+                    if let Some(value) = arguments.get(&name.name) {
+                        if !expansion.grow(result.len() + value.len(), &name.location, diagnostics)
+                        {
+                            break;
+                        }
                         result.push_str(value.as_str());
+                    } else if arguments.in_function {
+                        diagnostics.error(MemlError::UndefinedArgument {
+                            name: name.name.to_string(),
+                            location: name.location.clone(),
+                        });
                     } else {
-                        panic!(
-                            "{}",
-                            Error::new_from_span(
-                                ErrorVariant::<()>::CustomError {
-                                    message: "undefined argument".to_string()
-                                },
-                                pair.as_span(),
-                            )
-                        )
+                        diagnostics.error(MemlError::UnexpectedArgument {
+                            name: name.name.to_string(),
+                            location: name.location.clone(),
+                        });
                     }
                 } else {
-                    panic!("{}", Error::new_from_span(
-                        ErrorVariant::<()>::CustomError{
-                            message: "unexpected function argument (to access a constant use parentheses instead)".to_string()
-                        },
-                        string_component.as_span(),
-                    ))
+                    diagnostics.error(MemlError::UnexpectedArgument {
+                        name: name.name.to_string(),
+                        location: name.location.clone(),
+                    });
                 }
             }
-            _ => unimplemented!(),
         }
     }
 
-    return result;
+    result
 }
@@ -0,0 +1,736 @@
+/*
+meml – XML replacement written in Rust with the pest library <https://pest.rs>.
+Developed to be used in ygo_destiny <https://github.com/myuujiku/ygo_destiny/>.
+Copyright (C) 2022  myujiku
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published
+by the Free Software Foundation, either version 3 of the License,
+or (at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use super::*;
+
+/// A name as it was written in the source.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Ident {
+    pub(crate) name: String,
+    pub(crate) location: Location,
+}
+
+impl Ident {
+    pub fn compile(pair: Pair<Rule>) -> Self {
+        Self {
+            name: pair.as_str().to_string(),
+            location: Location::from_span(pair.as_span()),
+        }
+    }
+
+    pub fn set_path(&mut self, path: &Path) {
+        self.location.set_path(path);
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum StringPart {
+    Text(String),
+    /// `$(name)`
+    Constant(Ident),
+    /// `${name}`
+    Argument(Ident),
+}
+
+impl StringPart {
+    /// Compiles a `qtext`, `escape`, `sconst` or `sarg` pair.
+    pub fn compile(pair: Pair<Rule>) -> Result<Self, MemlError> {
+        Ok(match pair.as_rule() {
+            Rule::qtext => Self::Text(pair.as_str().to_string()),
+            Rule::escape => Self::Text(Self::unescape(pair)?.to_string()),
+            Rule::sconst => Self::Constant(Ident::compile(pair.into_inner().next().unwrap())),
+            Rule::sarg => Self::Argument(Ident::compile(pair.into_inner().next().unwrap())),
+            _ => unreachable!(),
+        })
+    }
+
+    fn unescape(pair: Pair<Rule>) -> Result<char, MemlError> {
+        let sequence = pair.as_str();
+
+        Ok(match &sequence[1..] {
+            "n" => '\n',
+            "t" => '\t',
+            "r" => '\r',
+            "0" => '\0',
+            // `\u{...}`, the other escapes are the character itself
+            code if code.starts_with('u') => u32::from_str_radix(&code[2..code.len() - 1], 16)
+                .ok()
+                .and_then(char::from_u32)
+                .ok_or_else(|| MemlError::InvalidEscape {
+                    sequence: sequence.to_string(),
+                    location: Location::from_span(pair.as_span()),
+                })?,
+            character => character.chars().next().unwrap(),
+        })
+    }
+}
+
+/// A string literal whose constants and arguments have not been substituted yet.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct StringTemplate {
+    pub(crate) parts: Vec<StringPart>,
+}
+
+impl StringTemplate {
+    /// Compiles a `string` or `argument` pair.
+    pub fn compile(pair: Pair<Rule>) -> Result<Self, MemlError> {
+        let inner = pair.into_inner().next().unwrap();
+
+        let parts = match inner.as_rule() {
+            Rule::raw_string => vec![StringPart::Text(
+                inner.into_inner().next().unwrap().as_str().to_string(),
+            )],
+            Rule::ml_string => return Self::compile_multiline(inner),
+            _ => inner
+                .into_inner()
+                .map(StringPart::compile)
+                .collect::<Result<Vec<StringPart>, MemlError>>()?,
+        };
+
+        Ok(Self { parts })
+    }
+
+    /// Compiles an `ml_string` pair.
+    ///
+    /// Like Rust's `indoc!`, the first line is removed if it is empty and the
+    /// common indentation of the other lines is stripped. Unlike `indoc!`,
+    /// the last line is removed as well if it is empty, so the closing quotes
+    /// can be on their own line without adding a trailing line break.
+    fn compile_multiline(pair: Pair<Rule>) -> Result<Self, MemlError> {
+        let mut inner_rules = pair.into_inner().peekable();
+        let fold = inner_rules
+            .next_if(|pair| pair.as_rule() == Rule::fold)
+            .is_some();
+
+        // Split the source into lines. Text from escapes and interpolations is
+        // never treated as indentation or as a line break.
+        let mut lines = vec![Vec::new()];
+        for pair in inner_rules.next().unwrap().into_inner() {
+            if pair.as_rule() == Rule::ml_text {
+                for (i, line) in pair.as_str().split('\n').enumerate() {
+                    if i > 0 {
+                        // Also strip the `\r` of Windows line breaks
+                        let previous = lines.last_mut().unwrap();
+                        if let Some(Line::Source(text)) = previous.last_mut() {
+                            if text.ends_with('\r') {
+                                text.pop();
+                            }
+                        }
+                        lines.push(Vec::new());
+                    }
+                    lines
+                        .last_mut()
+                        .unwrap()
+                        .push(Line::Source(line.to_string()));
+                }
+            } else {
+                lines
+                    .last_mut()
+                    .unwrap()
+                    .push(Line::Part(StringPart::compile(pair)?));
+            }
+        }
+
+        let is_blank = |line: &Vec<Line>| {
+            line.iter().all(|piece| match piece {
+                Line::Source(text) => text.trim().is_empty(),
+                Line::Part(_) => false,
+            })
+        };
+
+        let first_blank = is_blank(&lines[0]);
+        if first_blank {
+            lines.remove(0);
+        }
+        if lines.len() > 1 && is_blank(lines.last().unwrap()) {
+            lines.pop();
+        }
+
+        // The first line only counts if it started on a line of its own
+        let skip = usize::from(!first_blank);
+        let indent = lines
+            .iter()
+            .skip(skip)
+            .filter(|line| !is_blank(line))
+            .map(|line| match line.first() {
+                Some(Line::Source(text)) => text.len() - text.trim_start_matches([' ', '\t']).len(),
+                _ => 0,
+            })
+            .min()
+            .unwrap_or(0);
+
+        let mut parts = Vec::new();
+        let mut text = String::new();
+        let mut previous_blank = true;
+
+        for (i, line) in lines.iter().enumerate() {
+            let blank = is_blank(line);
+
+            if fold {
+                // Lines are joined with spaces, empty lines become line breaks
+                if blank {
+                    text.push('\n');
+                } else if !previous_blank {
+                    text.push(' ');
+                }
+            } else if i > 0 {
+                text.push('\n');
+            }
+            previous_blank = blank;
+
+            if blank {
+                continue;
+            }
+
+            for (j, piece) in line.iter().enumerate() {
+                match piece {
+                    Line::Source(source) if j == 0 && i >= skip => {
+                        let stripped = source.trim_start_matches([' ', '\t']);
+                        let strip = (source.len() - stripped.len()).min(indent);
+                        text.push_str(&source[strip..]);
+                    }
+                    Line::Source(source) => text.push_str(source),
+                    Line::Part(StringPart::Text(value)) => text.push_str(value),
+                    Line::Part(part) => {
+                        if !text.is_empty() {
+                            parts.push(StringPart::Text(std::mem::take(&mut text)));
+                        }
+                        parts.push(part.clone());
+                    }
+                }
+            }
+        }
+
+        if !text.is_empty() {
+            parts.push(StringPart::Text(text));
+        }
+
+        Ok(Self { parts })
+    }
+
+    pub fn set_path(&mut self, path: &Path) {
+        for part in &mut self.parts {
+            match part {
+                StringPart::Text(_) => (),
+                StringPart::Constant(name) | StringPart::Argument(name) => name.set_path(path),
+            }
+        }
+    }
+}
+
+/// A piece of a line of a multi-line string.
+enum Line {
+    /// Text as written in the source, which may be indentation.
+    Source(String),
+    Part(StringPart),
+}
+
+impl fmt::Display for StringTemplate {
+    /// Writes the template as it would appear between quotes in the source.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for part in &self.parts {
+            match part {
+                StringPart::Text(text) => {
+                    for character in text.chars() {
+                        match character {
+                            '\n' => write!(f, "\\n")?,
+                            '\t' => write!(f, "\\t")?,
+                            '\r' => write!(f, "\\r")?,
+                            '\0' => write!(f, "\\0")?,
+                            '"' | '\\' | '$' => write!(f, "\\{}", character)?,
+                            _ => write!(f, "{}", character)?,
+                        }
+                    }
+                }
+                StringPart::Constant(name) => write!(f, "$({})", name.name)?,
+                StringPart::Argument(name) => write!(f, "${{{}}}", name.name)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// An argument of a function call, optionally passed by name (`name: "value"`).
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ArgumentTemplate {
+    pub(crate) name: Option<Ident>,
+    pub(crate) value: StringTemplate,
+}
+
+impl ArgumentTemplate {
+    /// Compiles an `argument` or `named_arg` pair.
+    pub fn compile(pair: Pair<Rule>) -> Result<Self, MemlError> {
+        Ok(match pair.as_rule() {
+            Rule::named_arg => {
+                let mut inner_rules = pair.into_inner();
+                Self {
+                    name: Some(Ident::compile(inner_rules.next().unwrap())),
+                    value: StringTemplate::compile(inner_rules.next().unwrap())?,
+                }
+            }
+            _ => Self {
+                name: None,
+                value: StringTemplate::compile(pair)?,
+            },
+        })
+    }
+
+    pub fn set_path(&mut self, path: &Path) {
+        if let Some(name) = &mut self.name {
+            name.set_path(path);
+        }
+        self.value.set_path(path);
+    }
+}
+
+/// Checks that a `name` pair is a valid XML name. The grammar only allows
+/// ASCII letters, digits, `_` and `-`, of which XML names cannot start with a
+/// digit or `-`.
+pub(crate) fn validate_name(kind: &str, pair: &Pair<Rule>) -> Result<(), MemlError> {
+    let name = pair.as_str();
+
+    if name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+        Ok(())
+    } else {
+        Err(MemlError::InvalidName {
+            kind: kind.to_string(),
+            name: name.to_string(),
+            location: Location::from_span(pair.as_span()),
+        })
+    }
+}
+
+/// An attribute, optionally only added if its condition is true
+/// (`name: "value" if condition`).
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AttributeTemplate {
+    /// Namespace prefix of the attribute, empty if it has none.
+    pub(crate) namespace: String,
+    pub(crate) name: String,
+    pub(crate) value: StringTemplate,
+    pub(crate) condition: Option<Condition>,
+    pub(crate) location: Location,
+}
+
+impl AttributeTemplate {
+    pub fn compile(pair: Pair<Rule>) -> Result<Self, MemlError> {
+        let location = Location::from_span(pair.as_span());
+        let mut inner_rules = pair.into_inner().peekable();
+
+        let namespace = match inner_rules.next_if(|pair| pair.as_rule() == Rule::attr_prefix) {
+            Some(prefix) => {
+                validate_name("namespace prefix", &prefix)?;
+                prefix.as_str().to_string()
+            }
+            None => String::new(),
+        };
+        let name = inner_rules.next().unwrap();
+        validate_name("attribute", &name)?;
+        // Declarations are added to the output from the `namespace`
+        // definitions, so they cannot be written by hand
+        if namespace == "xmlns" || (namespace.is_empty() && name.as_str() == "xmlns") {
+            return Err(MemlError::InvalidNamespace {
+                reason: "namespaces are declared with `namespace prefix = \"uri\"` \
+                    instead of `xmlns` attributes"
+                    .to_string(),
+                location,
+            });
+        }
+
+        Ok(Self {
+            namespace,
+            name: name.as_str().to_string(),
+            value: StringTemplate::compile(inner_rules.next().unwrap())?,
+            condition: inner_rules.next().map(Condition::compile).transpose()?,
+            location,
+        })
+    }
+
+    pub fn set_path(&mut self, path: &Path) {
+        self.location.set_path(path);
+        self.value.set_path(path);
+        if let Some(condition) = &mut self.condition {
+            condition.set_path(path);
+        }
+    }
+}
+
+/// An element whose contents have not been evaluated yet.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ElementTemplate {
+    pub(crate) namespace: String,
+    pub(crate) name: String,
+    pub(crate) attributes: Vec<AttributeTemplate>,
+    pub(crate) children: Vec<ChildTemplate>,
+    pub(crate) location: Location,
+}
+
+impl ElementTemplate {
+    pub fn compile(pair: Pair<Rule>) -> Result<Self, MemlError> {
+        let location = Location::from_span(pair.as_span());
+        let mut inner_rules = pair.into_inner();
+
+        let namespace = inner_rules.next().unwrap();
+        if !namespace.as_str().is_empty() {
+            validate_name("namespace prefix", &namespace)?;
+        }
+        let namespace = namespace.as_str().to_string();
+        let name = inner_rules.next().unwrap();
+        validate_name("element", &name)?;
+        let name = name.as_str().to_string();
+
+        let attributes = inner_rules
+            .next()
+            .unwrap()
+            .into_inner()
+            .map(AttributeTemplate::compile)
+            .collect::<Result<Vec<AttributeTemplate>, MemlError>>()?;
+
+        let children = inner_rules
+            .next()
+            .unwrap()
+            .into_inner()
+            .map(ChildTemplate::compile)
+            .collect::<Result<Vec<ChildTemplate>, MemlError>>()?;
+
+        Ok(Self {
+            namespace,
+            name,
+            attributes,
+            children,
+            location,
+        })
+    }
+
+    /// Sets the file path of every location in the template that has none, so
+    /// errors point to the right file when it is used from another one.
+    pub fn set_path(&mut self, path: &Path) {
+        self.location.set_path(path);
+        for attribute in &mut self.attributes {
+            attribute.set_path(path);
+        }
+        for child in &mut self.children {
+            child.set_path(path);
+        }
+    }
+}
+
+/// Anything that can appear in the children of an element or at the top level.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ChildTemplate {
+    Element(ElementTemplate),
+    /// A text node, written without escaping if it is trusted markup (`!"..."`).
+    Text {
+        value: StringTemplate,
+        raw: bool,
+        location: Location,
+    },
+    /// Use of an element constant.
+    Constant(Ident),
+    /// Function call with its unevaluated arguments and the children passed
+    /// in its body.
+    Call {
+        name: Ident,
+        arguments: Vec<ArgumentTemplate>,
+        body: Vec<ChildTemplate>,
+        location: Location,
+    },
+    /// `${children}`, replaced by the children passed to the enclosing function.
+    Slot(Location),
+    /// `for name in source { ... }`, expanded once per value of the source.
+    For {
+        variable: String,
+        source: LoopSource,
+        children: Vec<ChildTemplate>,
+        location: Location,
+    },
+    /// `if condition { ... } else if condition { ... } else { ... }`, expanded
+    /// to the children of the first branch whose condition is true.
+    If {
+        branches: Vec<(Condition, Vec<ChildTemplate>)>,
+        otherwise: Vec<ChildTemplate>,
+        location: Location,
+    },
+    /// `[list] item => element`, expanded once per item of the list.
+    List {
+        list: Ident,
+        item: String,
+        template: ElementTemplate,
+    },
+}
+
+impl ChildTemplate {
+    pub fn compile(pair: Pair<Rule>) -> Result<Self, MemlError> {
+        match pair.as_rule() {
+            Rule::element => Ok(Self::Element(ElementTemplate::compile(pair)?)),
+            Rule::text => {
+                let location = Location::from_span(pair.as_span());
+                let mut inner_rules = pair.into_inner().peekable();
+                let raw = inner_rules
+                    .next_if(|pair| pair.as_rule() == Rule::markup)
+                    .is_some();
+
+                Ok(Self::Text {
+                    value: StringTemplate::compile(inner_rules.next().unwrap())?,
+                    raw,
+                    location,
+                })
+            }
+            Rule::const_use => Ok(Self::Constant(Ident::compile(pair))),
+            Rule::func_use => {
+                let location = Location::from_span(pair.as_span());
+                let mut inner_rules = pair.into_inner();
+                let name = Ident::compile(inner_rules.next().unwrap());
+                let mut arguments = Vec::new();
+                let mut body = Vec::new();
+
+                for pair in inner_rules {
+                    match pair.as_rule() {
+                        Rule::args => {
+                            arguments = pair
+                                .into_inner()
+                                .map(ArgumentTemplate::compile)
+                                .collect::<Result<Vec<ArgumentTemplate>, MemlError>>()?
+                        }
+                        Rule::block => body = Self::compile_block(pair)?,
+                        _ => unreachable!(),
+                    }
+                }
+
+                Ok(Self::Call {
+                    name,
+                    arguments,
+                    body,
+                    location,
+                })
+            }
+            Rule::slot => Ok(Self::Slot(Location::from_span(pair.as_span()))),
+            Rule::for_loop => {
+                let location = Location::from_span(pair.as_span());
+                let mut inner_rules = pair.into_inner();
+
+                Ok(Self::For {
+                    variable: inner_rules.next().unwrap().as_str().to_string(),
+                    source: LoopSource::compile(inner_rules.next().unwrap())?,
+                    children: Self::compile_block(inner_rules.next().unwrap())?,
+                    location,
+                })
+            }
+            Rule::conditional => {
+                let location = Location::from_span(pair.as_span());
+                let mut inner_rules = pair.into_inner();
+                let mut branches = vec![(
+                    Condition::compile(inner_rules.next().unwrap())?,
+                    Self::compile_block(inner_rules.next().unwrap())?,
+                )];
+                let mut otherwise = Vec::new();
+
+                for pair in inner_rules {
+                    match pair.as_rule() {
+                        Rule::else_if => {
+                            let mut inner_rules = pair.into_inner();
+                            branches.push((
+                                Condition::compile(inner_rules.next().unwrap())?,
+                                Self::compile_block(inner_rules.next().unwrap())?,
+                            ));
+                        }
+                        Rule::else_block => {
+                            otherwise = Self::compile_block(pair.into_inner().next().unwrap())?
+                        }
+                        _ => unreachable!(),
+                    }
+                }
+
+                Ok(Self::If {
+                    branches,
+                    otherwise,
+                    location,
+                })
+            }
+            Rule::list_use => {
+                let mut inner_rules = pair.into_inner();
+
+                Ok(Self::List {
+                    list: Ident::compile(inner_rules.next().unwrap()),
+                    item: inner_rules.next().unwrap().as_str().to_string(),
+                    template: ElementTemplate::compile(inner_rules.next().unwrap())?,
+                })
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    /// Compiles the children of a `block` pair.
+    fn compile_block(pair: Pair<Rule>) -> Result<Vec<Self>, MemlError> {
+        pair.into_inner()
+            .next()
+            .unwrap()
+            .into_inner()
+            .map(Self::compile)
+            .collect()
+    }
+
+    /// Location of the template in the source.
+    pub(crate) fn location(&self) -> &Location {
+        match self {
+            Self::Element(template) => &template.location,
+            Self::Constant(name) => &name.location,
+            Self::List { list, .. } => &list.location,
+            Self::Text { location, .. }
+            | Self::Call { location, .. }
+            | Self::Slot(location)
+            | Self::For { location, .. }
+            | Self::If { location, .. } => location,
+        }
+    }
+
+    pub fn set_path(&mut self, path: &Path) {
+        match self {
+            Self::Element(template) => template.set_path(path),
+            Self::Text {
+                value, location, ..
+            } => {
+                value.set_path(path);
+                location.set_path(path);
+            }
+            Self::Constant(name) => name.set_path(path),
+            Self::Call {
+                name,
+                arguments,
+                body,
+                location,
+            } => {
+                name.set_path(path);
+                location.set_path(path);
+                for argument in arguments {
+                    argument.set_path(path);
+                }
+                for child in body {
+                    child.set_path(path);
+                }
+            }
+            Self::Slot(location) => location.set_path(path),
+            Self::For {
+                source,
+                children,
+                location,
+                ..
+            } => {
+                source.set_path(path);
+                location.set_path(path);
+                for child in children {
+                    child.set_path(path);
+                }
+            }
+            Self::If {
+                branches,
+                otherwise,
+                location,
+            } => {
+                location.set_path(path);
+                for (condition, children) in branches {
+                    condition.set_path(path);
+                    for child in children {
+                        child.set_path(path);
+                    }
+                }
+                for child in otherwise {
+                    child.set_path(path);
+                }
+            }
+            Self::List { list, template, .. } => {
+                list.set_path(path);
+                template.set_path(path);
+            }
+        }
+    }
+}
+
+/// The values a `for` loop iterates over.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum LoopSource {
+    /// `start..end` or `start..=end`, where both bounds evaluate to integers.
+    Range {
+        start: StringTemplate,
+        end: StringTemplate,
+        inclusive: bool,
+        location: Location,
+    },
+    /// `[list]`
+    List(Ident),
+}
+
+impl LoopSource {
+    /// Compiles a `range` or `loop_list` pair.
+    pub fn compile(pair: Pair<Rule>) -> Result<Self, MemlError> {
+        Ok(match pair.as_rule() {
+            Rule::range => {
+                let location = Location::from_span(pair.as_span());
+                let mut inner_rules = pair.into_inner();
+
+                let start = Self::bound(inner_rules.next().unwrap())?;
+                let inclusive = inner_rules.next().unwrap().as_rule() == Rule::inclusive;
+                let end = Self::bound(inner_rules.next().unwrap())?;
+
+                Self::Range {
+                    start,
+                    end,
+                    inclusive,
+                    location,
+                }
+            }
+            Rule::loop_list => Self::List(Ident::compile(pair.into_inner().next().unwrap())),
+            _ => unreachable!(),
+        })
+    }
+
+    fn bound(pair: Pair<Rule>) -> Result<StringTemplate, MemlError> {
+        let inner = pair.into_inner().next().unwrap();
+        let part = match inner.as_rule() {
+            Rule::integer => StringPart::Text(inner.as_str().to_string()),
+            _ => StringPart::compile(inner)?,
+        };
+
+        Ok(StringTemplate { parts: vec![part] })
+    }
+
+    pub fn set_path(&mut self, path: &Path) {
+        match self {
+            Self::Range {
+                start,
+                end,
+                location,
+                ..
+            } => {
+                start.set_path(path);
+                end.set_path(path);
+                location.set_path(path);
+            }
+            Self::List(list) => list.set_path(path),
+        }
+    }
+}
@@ -0,0 +1,178 @@
+/*
+meml – XML replacement written in Rust with the pest library <https://pest.rs>.
+Developed to be used in ygo_destiny <https://github.com/myuujiku/ygo_destiny/>.
+Copyright (C) 2022  myujiku
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published
+by the Free Software Foundation, either version 3 of the License,
+or (at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+//! TOML output, see [`TomlSerializer`] for how elements are mapped.
+
+use std::io::{self, Write};
+
+use super::{
+    json::write_string,
+    mapping::{is_bare_key, table, Entry, Value},
+    serializer::{io_error, serialize_to_string},
+    *,
+};
+
+/// Writes elements as a TOML document.
+///
+/// Elements are mapped to nested tables the same way as by
+/// [`YamlSerializer`]. Tables are written as `[table]` sections and arrays of
+/// tables as `[[array]]` sections, so repeated elements must either all be
+/// text or all be tables, otherwise [`MemlError::UnsupportedOutput`] is
+/// returned.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TomlSerializer;
+
+impl Serializer for TomlSerializer {
+    fn serialize(&self, elements: &[Element], writer: &mut dyn Write) -> Result<(), MemlError> {
+        let entries = table(&elements.iter().collect::<Vec<&Element>>(), "TOML", "")?;
+
+        check_table(&entries, &[])?;
+        write_table(writer, &entries, &[], &mut true).map_err(io_error)
+    }
+}
+
+/// Writes `elements` as a TOML document. See [`TomlSerializer`] for how
+/// elements are mapped.
+pub fn to_toml(elements: &[Element]) -> Result<String, MemlError> {
+    serialize_to_string(&TomlSerializer, elements)
+}
+
+/// Checks that no array mixes text and tables, which cannot be written as
+/// either an inline array or an array of tables.
+fn check_table(entries: &[Entry], keys: &[&str]) -> Result<(), MemlError> {
+    for (key, value) in entries {
+        let keys = [keys, &[key.as_ref()]].concat();
+        match value {
+            Value::Table(entries) => check_table(entries, &keys)?,
+            Value::Array(items) => {
+                let text = items
+                    .iter()
+                    .filter(|item| matches!(item, Value::String(_)))
+                    .count();
+                if text != 0 && text != items.len() {
+                    return Err(MemlError::UnsupportedOutput {
+                        path: None,
+                        format: "TOML".to_string(),
+                        key: keys.join("."),
+                        reason: "elements with the same name must either all be text or all \
+                            be tables"
+                            .to_string(),
+                    });
+                }
+                for item in items {
+                    if let Value::Table(entries) = item {
+                        check_table(entries, &keys)?;
+                    }
+                }
+            }
+            Value::String(_) => (),
+        }
+    }
+
+    Ok(())
+}
+
+/// Writes the text values of `entries` first, since every key after a section
+/// header belongs to that section, then the tables and arrays of tables.
+///
+/// `empty` is whether nothing has been written yet.
+fn write_table(
+    writer: &mut dyn Write,
+    entries: &[Entry],
+    keys: &[&str],
+    empty: &mut bool,
+) -> io::Result<()> {
+    for (key, value) in entries {
+        match value {
+            Value::String(value) => {
+                write_key(writer, key)?;
+                writer.write_all(b" = ")?;
+                write_string(writer, value)?;
+                writer.write_all(b"\n")?;
+            }
+            Value::Array(items) if items.iter().all(|item| matches!(item, Value::String(_))) => {
+                write_key(writer, key)?;
+                writer.write_all(b" = [")?;
+                for (index, item) in items.iter().enumerate() {
+                    if index > 0 {
+                        writer.write_all(b", ")?;
+                    }
+                    if let Value::String(item) = item {
+                        write_string(writer, item)?;
+                    }
+                }
+                writer.write_all(b"]\n")?;
+            }
+            _ => continue,
+        }
+        *empty = false;
+    }
+
+    for (key, value) in entries {
+        let keys = [keys, &[key.as_ref()]].concat();
+        match value {
+            Value::Table(entries) => {
+                write_header(writer, "[", &keys, "]", empty)?;
+                write_table(writer, entries, &keys, empty)?;
+            }
+            Value::Array(items) => {
+                for item in items {
+                    if let Value::Table(entries) = item {
+                        write_header(writer, "[[", &keys, "]]", empty)?;
+                        write_table(writer, entries, &keys, empty)?;
+                    }
+                }
+            }
+            Value::String(_) => (),
+        }
+    }
+
+    Ok(())
+}
+
+fn write_header(
+    writer: &mut dyn Write,
+    open: &str,
+    keys: &[&str],
+    close: &str,
+    empty: &mut bool,
+) -> io::Result<()> {
+    if !*empty {
+        writer.write_all(b"\n")?;
+    }
+    *empty = false;
+
+    writer.write_all(open.as_bytes())?;
+    for (index, key) in keys.iter().enumerate() {
+        if index > 0 {
+            writer.write_all(b".")?;
+        }
+        write_key(writer, key)?;
+    }
+    writer.write_all(close.as_bytes())?;
+    writer.write_all(b"\n")
+}
+
+fn write_key(writer: &mut dyn Write, key: &str) -> io::Result<()> {
+    if is_bare_key(key) {
+        writer.write_all(key.as_bytes())
+    } else {
+        write_string(writer, key)
+    }
+}
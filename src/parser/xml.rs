@@ -0,0 +1,296 @@
+/*
+meml – XML replacement written in Rust with the pest library <https://pest.rs>.
+Developed to be used in ygo_destiny <https://github.com/myuujiku/ygo_destiny/>.
+Copyright (C) 2022  myujiku
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published
+by the Free Software Foundation, either version 3 of the License,
+or (at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use std::io::{self, Write};
+
+use super::{
+    serializer::{io_error, serialize_to_string, write_escaped, write_qualified, write_repeated},
+    *,
+};
+
+/// Indentation of one nesting level.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Indent {
+    Spaces(usize),
+    Tabs(usize),
+}
+
+impl Indent {
+    fn write(self, writer: &mut dyn Write, depth: usize) -> io::Result<()> {
+        match self {
+            Self::Spaces(width) => write_repeated(writer, " ", width * depth),
+            Self::Tabs(width) => write_repeated(writer, "\t", width * depth),
+        }
+    }
+
+    /// Width of `depth` levels of indentation in characters.
+    fn width(self, depth: usize) -> usize {
+        match self {
+            Self::Spaces(width) | Self::Tabs(width) => width * depth,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum LineEnding {
+    #[default]
+    Lf,
+    CrLf,
+}
+
+impl LineEnding {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Lf => "\n",
+            Self::CrLf => "\r\n",
+        }
+    }
+}
+
+/// How elements are written as XML. The default writes everything on a
+/// single line, [`XmlOptions::pretty`] one element per line.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct XmlOptions {
+    /// Indentation of nested elements, `None` writes everything on one line.
+    ///
+    /// Elements that contain text are always written on one line, since
+    /// line breaks and indentation would change the text.
+    pub indent: Option<Indent>,
+    pub line_ending: LineEnding,
+    /// Whether to start with `<?xml version="1.0" encoding="UTF-8"?>`.
+    pub declaration: bool,
+    /// Indented opening tags longer than this many characters are written
+    /// with one attribute per line.
+    pub max_width: usize,
+}
+
+impl Default for XmlOptions {
+    fn default() -> Self {
+        Self {
+            indent: None,
+            line_ending: LineEnding::Lf,
+            declaration: false,
+            max_width: 100,
+        }
+    }
+}
+
+impl XmlOptions {
+    /// One element per line indented with two spaces, starting with the XML
+    /// declaration.
+    pub fn pretty() -> Self {
+        Self {
+            indent: Some(Indent::Spaces(2)),
+            declaration: true,
+            ..Default::default()
+        }
+    }
+}
+
+/// Writes elements as XML formatted according to its options.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct XmlSerializer {
+    pub options: XmlOptions,
+}
+
+impl Serializer for XmlSerializer {
+    fn serialize(&self, elements: &[Element], writer: &mut dyn Write) -> Result<(), MemlError> {
+        write_document(writer, elements, &self.options).map_err(io_error)
+    }
+}
+
+impl Node {
+    pub fn as_xml(&self) -> String {
+        match self {
+            Self::Element(element) => element.as_xml(),
+            Self::Text(text) => {
+                let mut output = Vec::new();
+                // Writing to a `Vec` cannot fail
+                write_escaped(&mut output, text, escape_text).unwrap();
+                String::from_utf8(output).unwrap()
+            }
+            Self::Raw(text) => text.to_string(),
+        }
+    }
+}
+
+impl Element {
+    pub fn as_xml(&self) -> String {
+        let mut output = Vec::new();
+        // Writing to a `Vec` cannot fail
+        write_element(&mut output, self, &XmlOptions::default(), 0).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+}
+
+/// Writes `elements` as XML formatted according to `options`.
+pub fn to_xml(elements: &[Element], options: &XmlOptions) -> String {
+    serialize_to_string(&XmlSerializer { options: *options }, elements).unwrap()
+}
+
+fn write_document(
+    writer: &mut dyn Write,
+    elements: &[Element],
+    options: &XmlOptions,
+) -> io::Result<()> {
+    let line_ending = options.line_ending.as_str().as_bytes();
+
+    if options.declaration {
+        writer.write_all(b"<?xml version=\"1.0\" encoding=\"UTF-8\"?>")?;
+        writer.write_all(line_ending)?;
+    }
+    for element in elements {
+        write_element(writer, element, options, 0)?;
+        if options.indent.is_some() {
+            writer.write_all(line_ending)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn write_element(
+    writer: &mut dyn Write,
+    element: &Element,
+    options: &XmlOptions,
+    depth: usize,
+) -> io::Result<()> {
+    let line_ending = options.line_ending.as_str().as_bytes();
+    let newline = |writer: &mut dyn Write, depth| match options.indent {
+        Some(indent) => {
+            writer.write_all(line_ending)?;
+            indent.write(writer, depth)
+        }
+        None => Ok(()),
+    };
+
+    // Namespace declarations are written like attributes
+    let declarations = element.namespaces().iter().map(|(prefix, uri)| {
+        if prefix.is_empty() {
+            ("", "xmlns", uri.as_str())
+        } else {
+            ("xmlns", prefix.as_str(), uri.as_str())
+        }
+    });
+    let attributes = declarations
+        .chain(
+            element
+                .attributes()
+                .iter()
+                .map(|attribute| (attribute.namespace(), attribute.name(), attribute.value())),
+        )
+        .collect::<Vec<(&str, &str, &str)>>();
+
+    let qualified_width = |prefix: &str, name: &str| {
+        name.chars().count()
+            + if prefix.is_empty() {
+                0
+            } else {
+                prefix.chars().count() + 1
+            }
+    };
+    let width = options.indent.map_or(0, |indent| indent.width(depth))
+        + qualified_width(element.namespace(), element.name())
+        + attributes
+            .iter()
+            .map(|(prefix, name, value)| {
+                // ` name="value"`
+                qualified_width(prefix, name) + escaped_width(value, escape_attribute) + 4
+            })
+            .sum::<usize>()
+        + 2;
+    let wrap = options.indent.is_some() && attributes.len() > 1 && width > options.max_width;
+
+    writer.write_all(b"<")?;
+    write_qualified(writer, element.namespace(), element.name())?;
+    for (prefix, name, value) in &attributes {
+        if wrap {
+            newline(writer, depth + 1)?;
+        } else {
+            writer.write_all(b" ")?;
+        }
+        write_qualified(writer, prefix, name)?;
+        writer.write_all(b"=\"")?;
+        write_escaped(writer, value, escape_attribute)?;
+        writer.write_all(b"\"")?;
+    }
+
+    let nodes = element.nodes();
+    if nodes.iter().all(|node| match node {
+        Node::Text(text) | Node::Raw(text) => text.is_empty(),
+        Node::Element(_) => false,
+    }) {
+        return writer.write_all(b"/>");
+    }
+    writer.write_all(b">")?;
+
+    // Elements that contain text are written on one line
+    let block = nodes.iter().all(|node| matches!(node, Node::Element(_)));
+    for node in nodes {
+        match node {
+            Node::Element(child) => {
+                if block {
+                    newline(writer, depth + 1)?;
+                }
+                write_element(writer, child, options, depth + 1)?;
+            }
+            Node::Text(text) => write_escaped(writer, text, escape_text)?,
+            Node::Raw(text) => writer.write_all(text.as_bytes())?,
+        }
+    }
+    if block {
+        newline(writer, depth)?;
+    }
+
+    writer.write_all(b"</")?;
+    write_qualified(writer, element.namespace(), element.name())?;
+    writer.write_all(b">")
+}
+
+/// Width of `text` in characters after escaping.
+fn escaped_width(text: &str, escape: fn(char) -> Option<&'static str>) -> usize {
+    text.chars()
+        .map(|character| escape(character).map_or(1, str::len))
+        .sum()
+}
+
+/// Escapes text content, `>` included so `]]>` cannot appear.
+fn escape_text(character: char) -> Option<&'static str> {
+    match character {
+        '&' => Some("&amp;"),
+        '<' => Some("&lt;"),
+        '>' => Some("&gt;"),
+        _ => None,
+    }
+}
+
+/// Escapes an attribute value for double quotes. Whitespace other than spaces
+/// is written as character references, since parsers replace it with spaces.
+fn escape_attribute(character: char) -> Option<&'static str> {
+    match character {
+        '"' => Some("&quot;"),
+        '\n' => Some("&#10;"),
+        '\r' => Some("&#13;"),
+        '\t' => Some("&#9;"),
+        _ => escape_text(character),
+    }
+}
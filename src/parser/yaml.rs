@@ -0,0 +1,137 @@
+/*
+meml – XML replacement written in Rust with the pest library <https://pest.rs>.
+Developed to be used in ygo_destiny <https://github.com/myuujiku/ygo_destiny/>.
+Copyright (C) 2022  myujiku
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published
+by the Free Software Foundation, either version 3 of the License,
+or (at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+//! YAML output, see [`YamlSerializer`] for how elements are mapped.
+
+use std::io::{self, Write};
+
+use super::{
+    json::write_string,
+    mapping::{is_bare_key, table, Entry, Value},
+    serializer::{io_error, serialize_to_string, write_repeated},
+    *,
+};
+
+/// Writes elements as a YAML document, indented with two spaces.
+///
+/// Elements are mapped to nested tables:
+///
+/// - An element without attributes and child elements is its text, e.g.
+///   `monster { "normal" }` becomes `monster: "normal"`.
+/// - Any other element is a table. Its attributes and child elements are keys
+///   (with prefixes, e.g. `xml:lang`), its text is the `$text` key if it has
+///   any and no child elements.
+/// - Child elements with the same name are collected into an array in the
+///   order they appear, a name that only appears once is a single value.
+/// - The top-level elements are the keys of the root table.
+///
+/// An attribute with the same name as a child element cannot be written,
+/// and neither can mixed content (`p { "Hello " b { "world" } }`), since the
+/// position of the text between the child elements would be lost. Both are
+/// reported as [`MemlError::UnsupportedOutput`]. If an attribute name is
+/// repeated, which only manifest sections allow, the first value is used.
+///
+/// Values are always double-quoted, so text such as `yes` or `1.0` stays a
+/// string.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct YamlSerializer;
+
+impl Serializer for YamlSerializer {
+    fn serialize(&self, elements: &[Element], writer: &mut dyn Write) -> Result<(), MemlError> {
+        let entries = table(&elements.iter().collect::<Vec<&Element>>(), "YAML", "")?;
+
+        if entries.is_empty() {
+            writer.write_all(b"{}\n").map_err(io_error)
+        } else {
+            write_table(writer, &entries, 0, false).map_err(io_error)
+        }
+    }
+}
+
+/// Writes `elements` as a YAML document, indented with two spaces. See
+/// [`YamlSerializer`] for how elements are mapped.
+pub fn to_yaml(elements: &[Element]) -> Result<String, MemlError> {
+    serialize_to_string(&YamlSerializer, elements)
+}
+
+/// Writes one `key: value` line per entry. If `inline`, the first entry
+/// continues the current line, e.g. after the `- ` of an array item.
+fn write_table(
+    writer: &mut dyn Write,
+    entries: &[Entry],
+    indent: usize,
+    inline: bool,
+) -> io::Result<()> {
+    for (index, (key, value)) in entries.iter().enumerate() {
+        if index > 0 || !inline {
+            write_repeated(writer, " ", indent)?;
+        }
+        write_key(writer, key)?;
+        writer.write_all(b":")?;
+
+        match value {
+            Value::Table(entries) if !entries.is_empty() => {
+                writer.write_all(b"\n")?;
+                write_table(writer, entries, indent + 2, false)?;
+            }
+            Value::Array(items) => {
+                writer.write_all(b"\n")?;
+                for item in items {
+                    write_repeated(writer, " ", indent + 2)?;
+                    writer.write_all(b"- ")?;
+                    match item {
+                        Value::Table(entries) if !entries.is_empty() => {
+                            write_table(writer, entries, indent + 4, true)?
+                        }
+                        _ => write_inline(writer, item)?,
+                    }
+                }
+            }
+            _ => {
+                writer.write_all(b" ")?;
+                write_inline(writer, value)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Writes a string or an empty table followed by a line break.
+fn write_inline(writer: &mut dyn Write, value: &Value) -> io::Result<()> {
+    match value {
+        Value::String(value) => write_string(writer, value)?,
+        _ => writer.write_all(b"{}")?,
+    }
+    writer.write_all(b"\n")
+}
+
+fn write_key(writer: &mut dyn Write, key: &str) -> io::Result<()> {
+    // Plain scalars like `yes` or `null` would not be read back as strings
+    let reserved = matches!(
+        key.to_ascii_lowercase().as_str(),
+        "y" | "n" | "yes" | "no" | "on" | "off" | "true" | "false" | "null"
+    );
+
+    if is_bare_key(key) && !reserved && key.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        writer.write_all(key.as_bytes())
+    } else {
+        write_string(writer, key)
+    }
+}
@@ -19,7 +19,1375 @@ along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
 use super::*;
 
+fn to_xml(source: &str) -> Result<String, MemlError> {
+    Ok(parse_str(source)?
+        .elements()
+        .iter()
+        .map(|item| item.as_xml())
+        .collect())
+}
+
 #[test]
 fn manifest_test() {
-    parse_manifest("src/tests/meta.meml");
+    parse_manifest("src/tests/meta.meml").unwrap();
+
+    // Unchanged outputs are not written again
+    let modified = || {
+        fs::metadata("src/tests/out/lists.xml")
+            .unwrap()
+            .modified()
+            .unwrap()
+    };
+    let before = modified();
+    parse_manifest("src/tests/meta.meml").unwrap();
+    assert_eq!(modified(), before);
+
+    assert_eq!(
+        fs::read_to_string("src/tests/out/lists.xml").unwrap(),
+        "<deck><card type=\"monster\"/><card type=\"spell\"/><card type=\"trap\"/>\
+         <monster>normal</monster><monster>effect</monster></deck>"
+    );
+
+    let json = fs::read_to_string("src/tests/out/lists.json").unwrap();
+    assert!(
+        json.starts_with("[\n  {\n    \"name\": \"deck\",\n"),
+        "{}",
+        json
+    );
+    assert!(json.contains(
+        "{\n        \"name\": \"card\",\n        \"namespace\": \"\",\n        \
+         \"attributes\": {\n          \"type\": \"monster\"\n        },\n        \
+         \"children\": [],\n        \"text\": \"\"\n      }"
+    ));
+
+    assert_eq!(
+        fs::read_to_string("src/tests/out/lists.yaml").unwrap(),
+        "deck:\n  card:\n    - type: \"monster\"\n    - type: \"spell\"\n    - type: \"trap\"\n  \
+         monster:\n    - \"normal\"\n    - \"effect\"\n"
+    );
+    assert_eq!(
+        fs::read_to_string("src/tests/out/lists.toml").unwrap(),
+        "[deck]\nmonster = [\"normal\", \"effect\"]\n\n[[deck.card]]\ntype = \"monster\"\n\n\
+         [[deck.card]]\ntype = \"spell\"\n\n[[deck.card]]\ntype = \"trap\"\n"
+    );
+    assert_eq!(
+        fs::read_to_string("src/tests/out/lists.pretty.xml").unwrap(),
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<deck>\n    <card type=\"monster\"/>\n    \
+         <card type=\"spell\"/>\n    <card type=\"trap\"/>\n    <monster>normal</monster>\n    \
+         <monster>effect</monster>\n</deck>\n"
+    );
+}
+
+#[test]
+fn missing_manifest() {
+    assert!(matches!(
+        parse_manifest("src/tests/missing.meml"),
+        Err(MemlError::Io { .. })
+    ));
+    for path in ["", "/"] {
+        assert!(matches!(parse_manifest(path), Err(MemlError::Io { .. })));
+    }
+}
+
+#[test]
+fn errors() {
+    assert!(matches!(to_xml("root {"), Err(MemlError::Syntax { .. })));
+
+    match to_xml("root {\n    child { \"$(missing)\" }\n}") {
+        Err(MemlError::UndefinedString { name, location }) => {
+            assert_eq!(name, "missing");
+            assert_eq!((location.line, location.column), (2, 16));
+        }
+        other => panic!("unexpected result: {:?}", other),
+    }
+
+    assert!(matches!(
+        to_xml("root { missing }"),
+        Err(MemlError::UndefinedElement { .. })
+    ));
+    assert!(matches!(
+        to_xml("root { missing(\"a\") }"),
+        Err(MemlError::UndefinedFunction { .. })
+    ));
+    assert!(matches!(
+        to_xml("root { a: \"${arg}\" }"),
+        Err(MemlError::UnexpectedArgument { .. })
+    ));
+    assert!(matches!(
+        to_xml("def f(a): e { \"${b}\" }\nf(\"x\")"),
+        Err(MemlError::UndefinedArgument { .. })
+    ));
+    assert!(matches!(
+        to_xml("def f(a b): e {}\nf(\"x\" \"y\" \"z\")"),
+        Err(MemlError::ArityMismatch {
+            expected: 2,
+            found: 3,
+            ..
+        })
+    ));
+    assert!(matches!(
+        to_xml("def f(a b): e {}\nf(\"x\")"),
+        Err(MemlError::MissingArgument { name, .. }) if name == "b"
+    ));
+    assert!(matches!(
+        to_xml("use <string> missing"),
+        Err(MemlError::UndefinedImport { .. })
+    ));
+}
+
+#[test]
+fn document() {
+    let document = parse_str(
+        "export def greeting: \"hello\"\n\
+         namespace ns = \"https://example.com/ns\"\n\
+         def item: item {}\n\
+         ns:root { id: \"1\" item child { \"$(greeting)\" } }\n\
+         second {}",
+    )
+    .unwrap();
+
+    assert!(document.exports()["strings"].contains_key("greeting"));
+    assert!(document.exports()["elements"].is_empty());
+
+    let [root, second] = document.elements() else {
+        panic!("expected two elements");
+    };
+    assert_eq!((root.namespace(), root.name()), ("ns", "root"));
+    assert_eq!(root.attribute("id"), Some("1"));
+    assert_eq!(root.attribute("missing"), None);
+    assert_eq!(root.children().count(), 2);
+    assert_eq!(root.children().nth(1).unwrap().content(), "hello");
+    assert_eq!(second.name(), "second");
+}
+
+#[test]
+fn document_from_file() {
+    let document = parse_file("src/tests/in/simple.meml").unwrap();
+    assert_eq!(document.elements()[0].name(), "element");
+
+    assert!(matches!(
+        parse_file("src/tests/in/missing.meml"),
+        Err(MemlError::Io { .. })
+    ));
+}
+
+#[test]
+fn owned_definitions() {
+    fn assert_owned<T: Clone + Send + Sync + 'static>(_: &T) {}
+
+    let exports = {
+        let source = String::from(
+            "export def greeting: \"hello\"\n\
+             export def item: item { \"$(greeting)\" }\n\
+             export def wrap(text): wrap { \"${text}\" }",
+        );
+        parse_str(&source).unwrap().into_exports()
+    };
+    assert_owned(&exports);
+
+    let exports = std::thread::spawn(move || exports).join().unwrap();
+    for (source, expected) in [
+        (
+            "use <string> greeting\nuse <element> item\nitem",
+            "<item>hello</item>",
+        ),
+        ("use <function> wrap\nwrap(\"a\")", "<wrap>a</wrap>"),
+    ] {
+        assert_eq!(
+            parse_str_with_imports(source, &exports).unwrap().elements()[0].as_xml(),
+            expected
+        );
+    }
+    assert_eq!(
+        to_xml("def item: item { \"a\" }\nitem\nitem").unwrap(),
+        "<item>a</item><item>a</item>"
+    );
+}
+
+#[test]
+fn export_scope() {
+    let exports = parse_str(
+        "namespace = \"https://example.com/cards\"\n\
+         namespace ui = \"https://example.com/ui\"\n\
+         def suffix: \"!\"\n\
+         def items: [\"a\" \"b\"]\n\
+         def helper: helper {}\n\
+         def label(text): label { \"${text}\" }\n\
+         export def greeting: \"hello\"\n\
+         export def item: item { \"$(greeting)$(suffix)\" }\n\
+         export def wrap(text=\"$(greeting)\"): wrap { if \"$(suffix)\" { \"${text}\" } }\n\
+         export def card(t): card { helper label(\"${t}\") for i in [items] { i { \"${i}\" } } }\n\
+         export def button: ui:button {}\n\
+         export def frame(): ui:frame { ${children} }",
+    )
+    .unwrap()
+    .into_exports();
+
+    // Exported definitions use the definitions of the file they were defined
+    // in, even if they are not imported or a local one has the same name
+    assert_eq!(
+        parse_str_with_imports(
+            "def greeting: \"local\"\n\
+             def helper: local {}\n\
+             use <element> item\n\
+             use <function> wrap\n\
+             use <function> card\n\
+             root { item wrap() card(\"x\") }",
+            &exports
+        )
+        .unwrap()
+        .elements()[0]
+            .as_xml(),
+        "<root><item xmlns=\"https://example.com/cards\" xmlns:ui=\"https://example.com/ui\">hello!</item>\
+         <wrap xmlns=\"https://example.com/cards\" xmlns:ui=\"https://example.com/ui\">hello</wrap>\
+         <card xmlns=\"https://example.com/cards\" xmlns:ui=\"https://example.com/ui\"><helper/><label>x</label><i>a</i><i>b</i></card></root>"
+    );
+
+    // Namespaces are declared where they differ from those of the importing
+    // file, and children passed to a function keep the namespaces of the caller
+    assert_eq!(
+        parse_str_with_imports(
+            "namespace = \"https://example.com/cards\"\n\
+             namespace ui = \"https://example.com/other\"\n\
+             use <element> button\n\
+             use <function> frame\n\
+             root { button frame() { ui:label {} } }\n\
+             button",
+            &exports
+        )
+        .unwrap()
+        .elements()
+        .iter()
+        .map(Element::as_xml)
+        .collect::<String>(),
+        "<root xmlns=\"https://example.com/cards\" xmlns:ui=\"https://example.com/other\">\
+         <ui:button xmlns:ui=\"https://example.com/ui\"/>\
+         <ui:frame xmlns:ui=\"https://example.com/ui\">\
+         <ui:label xmlns:ui=\"https://example.com/other\"/></ui:frame></root>\
+         <ui:button xmlns=\"https://example.com/cards\" xmlns:ui=\"https://example.com/ui\"/>"
+    );
+    assert_eq!(
+        parse_str_with_imports(
+            "namespace = \"https://example.com/default\"\nuse <element> button\nbutton",
+            &exports
+        )
+        .unwrap()
+        .elements()[0]
+            .as_xml(),
+        "<ui:button xmlns=\"https://example.com/cards\" xmlns:ui=\"https://example.com/ui\"/>"
+    );
+}
+
+#[test]
+fn lists() {
+    assert_eq!(
+        to_xml(
+            "def suffix: \"!\"\n\
+             def items: [\"a\" 'b$(suffix)']\n\
+             root { [items] item => child { name: \"${item}\" } }\n\
+             [items] x => top { \"${x}\" }"
+        )
+        .unwrap(),
+        "<root><child name=\"a\"/><child name=\"b!\"/></root><top>a</top><top>b!</top>"
+    );
+}
+
+#[test]
+fn include_rename() {
+    let exports = parse_str(
+        "export def title: \"Manifest title\"\n\
+         export def title_list: [\"a\"]\n\
+         export def label(text): label { \"${text}\" }",
+    )
+    .unwrap()
+    .into_exports();
+
+    let document = parse_str_with_imports(
+        "def title: \"Local title\"\n\
+         use <string> title as manifest_title\n\
+         use <function> label as caption\n\
+         root { local: \"$(title)\" imported: \"$(manifest_title)\" caption(\"hi\") }",
+        &exports,
+    )
+    .unwrap();
+    assert_eq!(
+        document.elements()[0].as_xml(),
+        "<root local=\"Local title\" imported=\"Manifest title\"><label>hi</label></root>"
+    );
+
+    match parse_str_with_imports("def title: \"a\"\nuse <string> title", &exports) {
+        Err(MemlError::DuplicateDefinition { name, location, .. }) => {
+            assert_eq!(name, "title");
+            assert_eq!((location.line, location.column), (2, 14));
+        }
+        other => panic!("unexpected result: {:?}", other),
+    }
+    assert!(matches!(
+        parse_str_with_imports("def other: \"a\"\nuse <string> title as other", &exports),
+        Err(MemlError::DuplicateDefinition { .. })
+    ));
+    match parse_str_with_imports("use <string> title as other\ndef other: \"a\"", &exports) {
+        Err(MemlError::DuplicateDefinition { name, location, .. }) => {
+            assert_eq!(name, "other");
+            assert_eq!((location.line, location.column), (2, 5));
+        }
+        other => panic!("unexpected result: {:?}", other),
+    }
+    assert!(matches!(
+        parse_str_with_imports("use <function> label as caption\ncaption(\"a\" \"b\")", &exports),
+        Err(MemlError::ArityMismatch { name, .. }) if name == "caption"
+    ));
+}
+
+#[test]
+fn diagnostics() {
+    match parse_str("root { a: \"$(one)\" two three(\"x\") }") {
+        Err(MemlError::Diagnostics(diagnostics)) => {
+            assert_eq!(diagnostics.error_count(), 3);
+            assert_eq!(diagnostics.summary(), "3 errors and 0 warnings");
+        }
+        other => panic!("unexpected result: {:?}", other),
+    }
+
+    let mut diagnostics = Diagnostics::new();
+    let document = parse_str_with_diagnostics(
+        "def a: \"1\"\ndef a: \"2\"\nroot { \"$(a)\" }",
+        &HashMap::new(),
+        Limits::default(),
+        &mut diagnostics,
+    );
+    assert_eq!(document.elements()[0].content(), "2");
+    assert_eq!(diagnostics.warning_count(), 1);
+    assert!(!diagnostics.has_errors());
+
+    // A single error keeps the warnings reported with it
+    match parse_str("def a: \"1\"\ndef a: \"2\"\nroot { missing }") {
+        Err(MemlError::Diagnostics(diagnostics)) => {
+            assert_eq!(diagnostics.summary(), "1 error and 1 warning");
+        }
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
+
+#[test]
+fn manifest_diagnostics() {
+    let Err(MemlError::Diagnostics(diagnostics)) = parse_manifest("src/tests/invalid.meml") else {
+        panic!("expected several errors");
+    };
+
+    // `first.meml` is only evaluated by the first section, the second one is
+    // skipped because of its unknown property
+    assert_eq!(diagnostics.error_count(), 6, "{}", diagnostics);
+    assert_eq!(diagnostics.warning_count(), 0, "{}", diagnostics);
+    assert!(diagnostics.iter().any(|diagnostic| matches!(
+        &diagnostic.error,
+        MemlError::InvalidManifestProperty { property, .. } if property == "colour"
+    )));
+    assert!(diagnostics.iter().any(|diagnostic| matches!(
+        &diagnostic.error,
+        MemlError::ArityMismatch { location, .. }
+            if location.path.as_deref() == Some(Path::new("src/tests/invalid/second.meml"))
+    )));
+    // `first.meml` replaces the constant it includes
+    assert!(diagnostics.iter().any(|diagnostic| matches!(
+        &diagnostic.error,
+        MemlError::DuplicateDefinition { name, .. } if name == "title"
+    )));
+}
+
+#[test]
+fn named_arguments() {
+    let functions = "def button(label icon): button { icon: \"${icon}\" \"${label}\" }\n";
+
+    assert_eq!(
+        to_xml(&format!(
+            "{}button(icon: \"save\" label: \"Save\")\nbutton(\"Open\" icon: \"open\")",
+            functions
+        ))
+        .unwrap(),
+        "<button icon=\"save\">Save</button><button icon=\"open\">Open</button>"
+    );
+
+    // Positional arguments have to come first
+    assert!(matches!(
+        to_xml(&format!("{}button(icon: \"save\" \"Save\")", functions)),
+        Err(MemlError::Syntax { .. })
+    ));
+
+    match to_xml(&format!("{}buton(\"Save\" \"save\")", functions)) {
+        Err(error @ MemlError::UndefinedFunction { .. }) => assert_eq!(
+            error.message(),
+            "undefined function `buton`; did you mean `button`?"
+        ),
+        other => panic!("unexpected result: {:?}", other),
+    }
+
+    match to_xml(&format!("{}button(\"Save\" icn: \"save\")", functions)) {
+        Err(error @ MemlError::UnknownArgument { .. }) => assert_eq!(
+            error.message(),
+            "function `button(label icon)` has no parameter `icn`; did you mean `icon`?"
+        ),
+        other => panic!("unexpected result: {:?}", other),
+    }
+
+    assert!(matches!(
+        to_xml(&format!("{}button(\"Save\" label: \"save\")", functions)),
+        Err(MemlError::DuplicateArgument { name, .. }) if name == "label"
+    ));
+    assert!(matches!(
+        to_xml(&format!("{}button(icon: \"save\")", functions)),
+        Err(MemlError::MissingArgument { signature, .. }) if signature == "button(label icon)"
+    ));
+}
+
+#[test]
+fn default_arguments() {
+    let functions = "def default_icon: \"none\"\n\
+        def button(label icon=\"$(default_icon)\" size = \"small\"): \
+        button { icon: \"${icon}\" size: \"${size}\" \"${label}\" }\n";
+
+    assert_eq!(
+        to_xml(&format!(
+            "{}button(\"A\")\nbutton(\"B\" \"save\")\nbutton(\"C\" size: \"large\")",
+            functions
+        ))
+        .unwrap(),
+        "<button icon=\"none\" size=\"small\">A</button>\
+         <button icon=\"save\" size=\"small\">B</button>\
+         <button icon=\"none\" size=\"large\">C</button>"
+    );
+
+    match to_xml(&format!("{}button(icon: \"save\")", functions)) {
+        Err(error @ MemlError::MissingArgument { .. }) => assert_eq!(
+            error.message(),
+            "function `button(label icon=\"$(default_icon)\" size=\"small\")` \
+             is missing an argument for `label`"
+        ),
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
+
+#[test]
+fn children() {
+    let functions = "def card_frame(title): frame {\n\
+            header { \"${title}\" }\n\
+            body { ${children} }\n\
+        }\n";
+
+    assert_eq!(
+        to_xml(&format!(
+            "{}card_frame(\"Title\") {{\n    label {{}}\n    image {{}}\n}}\ncard_frame(\"Empty\")",
+            functions
+        ))
+        .unwrap(),
+        "<frame><header>Title</header><body><label/><image/></body></frame>\
+         <frame><header>Empty</header><body/></frame>"
+    );
+
+    // Calls without arguments and children passed on to another function
+    assert_eq!(
+        to_xml(&format!(
+            "{}def panel(): panel {{ card_frame(\"Panel\") {{ ${{children}} }} }}\n\
+             panel() {{ label {{}} }}",
+            functions
+        ))
+        .unwrap(),
+        "<panel><frame><header>Panel</header><body><label/></body></frame></panel>"
+    );
+
+    assert!(matches!(
+        to_xml("root { ${children} }"),
+        Err(MemlError::UnexpectedArgument { name, .. }) if name == "children"
+    ));
+
+    // Loops outside of functions only bind their own variables
+    assert!(matches!(
+        to_xml("root { for i in 0..1 { ${children} } }"),
+        Err(MemlError::UnexpectedArgument { name, .. }) if name == "children"
+    ));
+    assert!(matches!(
+        to_xml("root { for i in 0..1 { \"${x}\" } }"),
+        Err(MemlError::UnexpectedArgument { name, .. }) if name == "x"
+    ));
+    assert!(matches!(
+        to_xml("def f(a): e { for i in 0..1 { \"${x}\" } }\nf(\"a\")"),
+        Err(MemlError::UndefinedArgument { name, .. }) if name == "x"
+    ));
+}
+
+#[test]
+fn scoping() {
+    // Arguments can be forwarded to other functions
+    assert_eq!(
+        to_xml(
+            "def inner(text): inner { \"${text}\" }\n\
+             def outer(text): outer { inner(\"<${text}>\") }\n\
+             outer(\"a\")"
+        )
+        .unwrap(),
+        "<outer><inner>&lt;a&gt;</inner></outer>"
+    );
+
+    // but a function only sees its own parameters, not those of its caller
+    assert!(matches!(
+        to_xml(
+            "def inner(): inner { \"${text}\" }\n\
+             def outer(text): outer { inner() }\n\
+             outer(\"a\")"
+        ),
+        Err(MemlError::UndefinedArgument { name, .. }) if name == "text"
+    ));
+
+    // Element constants are expanded where they are used
+    assert_eq!(
+        to_xml(
+            "def title: title { \"${text}\" }\n\
+             def page(text): page { title }\n\
+             page(\"a\")"
+        )
+        .unwrap(),
+        "<page><title>a</title></page>"
+    );
+    assert!(matches!(
+        to_xml("def title: title { \"${text}\" }\nroot { title }"),
+        Err(MemlError::UnexpectedArgument { .. })
+    ));
+
+    // List items shadow parameters with the same name for the list element
+    assert_eq!(
+        to_xml(
+            "def items: [\"x\" \"y\"]\n\
+             def list(item prefix): list {\n\
+                 [items] item => entry { \"${prefix}${item}\" }\n\
+                 last { \"${item}\" }\n\
+             }\n\
+             list(\"z\" \"-\")"
+        )
+        .unwrap(),
+        "<list><entry>-x</entry><entry>-y</entry><last>z</last></list>"
+    );
+
+    // Children passed to a function are evaluated in the scope of the caller
+    assert_eq!(
+        to_xml(
+            "def frame(text): frame { text: \"${text}\" ${children} }\n\
+             def page(text): page { frame(\"inner\") { label { \"${text}\" } } }\n\
+             page(\"outer\")"
+        )
+        .unwrap(),
+        "<page><frame text=\"inner\"><label>outer</label></frame></page>"
+    );
+}
+
+#[test]
+fn limits() {
+    match to_xml("def a: a { b }\ndef b: b { a }\nroot { a }") {
+        Err(error @ MemlError::RecursionCycle { .. }) => {
+            assert_eq!(error.message(), "`a` uses itself: `a` -> `b` -> `a`")
+        }
+        other => panic!("unexpected result: {:?}", other),
+    }
+    assert!(matches!(
+        to_xml("def f(): f { g() }\ndef g(): g { f() }\nf()"),
+        Err(MemlError::RecursionCycle { path, .. }) if path == ["f()", "g()", "f()"]
+    ));
+
+    // Every function expands the previous one ten times
+    let mut source = "def l0(): l {}\n".to_string();
+    for i in 1..=7 {
+        source.push_str(&format!(
+            "def l{}(): l {{ {} }}\n",
+            i,
+            format!("l{}() ", i - 1).repeat(10)
+        ));
+    }
+    source.push_str("l7()");
+    let parse = |source: &str, limits: Limits| {
+        let mut diagnostics = Diagnostics::new();
+        parse_str_with_diagnostics(source, &HashMap::new(), limits, &mut diagnostics);
+        diagnostics.into_error()
+    };
+    let default = Limits::default();
+
+    assert!(matches!(
+        parse(
+            &source,
+            Limits {
+                max_elements: 1000,
+                ..default
+            }
+        ),
+        MemlError::LimitExceeded { max: 1000, .. }
+    ));
+    assert!(matches!(
+        parse("a { b { c { d {} } } }", Limits { max_depth: 3, ..default }),
+        MemlError::LimitExceeded { limit, .. } if limit == "nesting depth"
+    ));
+    assert!(matches!(
+        parse("root { \"0123456789\" }", Limits { max_output_bytes: 10, ..default }),
+        MemlError::LimitExceeded { limit, .. } if limit == "output size in bytes"
+    ));
+
+    // Nested loops count every iteration even if they add no elements
+    assert!(matches!(
+        parse(
+            "root { for i in 0..900000 { for j in 0..900000 { if \"\" { a {} } } } }",
+            default
+        ),
+        MemlError::LimitExceeded { limit, max: 10_000_000, .. } if limit == "number of loop iterations"
+    ));
+    assert!(matches!(
+        parse(
+            "def l: [\"a\" \"b\" \"c\"]\nroot { for x in [l] { for y in [l] {} } }",
+            Limits { max_iterations: 10, ..default }
+        ),
+        MemlError::LimitExceeded { limit, max: 10, .. } if limit == "number of loop iterations"
+    ));
+
+    // Strings are checked while they are built, whether they are constants
+    // or arguments forwarded to other functions, and every level repeats the
+    // previous one ten times
+    let mut constants = "def s0: \"0123456789\"\n".to_string();
+    let mut functions = "def f0(x): e { \"${x}\" }\n".to_string();
+    for i in 1..=6 {
+        constants.push_str(&format!(
+            "def s{}: \"{}\"\n",
+            i,
+            format!("$(s{})", i - 1).repeat(10)
+        ));
+        functions.push_str(&format!(
+            "def f{}(x): e {{ f{}(\"{}\") }}\n",
+            i,
+            i - 1,
+            "${x}".repeat(10)
+        ));
+    }
+    constants.push_str("root { a: \"$(s6)\" }");
+    functions.push_str("f6(\"0123456789\")");
+    for source in [constants, functions] {
+        assert!(matches!(
+            parse(&source, Limits { max_output_bytes: 1000, ..default }),
+            MemlError::LimitExceeded { limit, max: 1000, .. } if limit == "output size in bytes"
+        ));
+    }
+}
+
+#[test]
+fn conditionals() {
+    let source = |mode: &str| {
+        format!(
+            "def mode: \"{}\"\n\
+             root {{\n\
+                 if $(mode) == \"debug\" {{ debug {{}} }}\n\
+                 else if $(mode) != \"\" && !($(mode) == \"release\" || $(mode) == \"test\") {{ other {{}} }}\n\
+                 else {{ release {{}} }}\n\
+                 if $(mode) {{ mode {{ \"$(mode)\" }} }}\n\
+             }}",
+            mode
+        )
+    };
+
+    assert_eq!(
+        to_xml(&source("debug")).unwrap(),
+        "<root><debug/><mode>debug</mode></root>"
+    );
+    assert_eq!(
+        to_xml(&source("custom")).unwrap(),
+        "<root><other/><mode>custom</mode></root>"
+    );
+    assert_eq!(
+        to_xml(&source("test")).unwrap(),
+        "<root><release/><mode>test</mode></root>"
+    );
+    assert_eq!(to_xml(&source("")).unwrap(), "<root><release/></root>");
+
+    // Conditional attributes and conditions on function arguments
+    assert_eq!(
+        to_xml(
+            "def button(label disabled=\"\"): button {\n\
+                 disabled: \"true\" if ${disabled}\n\
+                 kind: \"${label}\" if ${label} != \"OK\"\n\
+                 if !${disabled} { enabled {} }\n\
+             }\n\
+             button(\"OK\")\n\
+             button(\"Cancel\" disabled: \"yes\")"
+        )
+        .unwrap(),
+        "<button><enabled/></button><button disabled=\"true\" kind=\"Cancel\"/>"
+    );
+
+    // Conditions at the top level and errors in them
+    assert_eq!(to_xml("if \"a\" == \"a\" { root {} }").unwrap(), "<root/>");
+    assert!(matches!(
+        to_xml("root { if $(missing) { child {} } }"),
+        Err(MemlError::UndefinedString { .. })
+    ));
+}
+
+#[test]
+fn loops() {
+    assert_eq!(
+        to_xml("grid { for i in 1..4 { slot { index: \"${i}\" } } }").unwrap(),
+        "<grid><slot index=\"1\"/><slot index=\"2\"/><slot index=\"3\"/></grid>"
+    );
+
+    // Nested loops, bounds from constants and arguments, and loops at the top level
+    assert_eq!(
+        to_xml(
+            "def rows: \"2\"\n\
+             def table(columns): table {\n\
+                 for row in 1..=$(rows) { row { for column in 1..=${columns} { cell { \"${row}.${column}\" } } } }\n\
+             }\n\
+             table(\"2\")\n\
+             for i in 0..2 { item { \"${i}\" } }"
+        )
+        .unwrap(),
+        "<table><row><cell>1.1</cell><cell>1.2</cell></row>\
+         <row><cell>2.1</cell><cell>2.2</cell></row></table>\
+         <item>0</item><item>1</item>"
+    );
+
+    // Loops over lists with the position of the current item
+    assert_eq!(
+        to_xml(
+            "def cards: [\"monster\" \"spell\" \"trap\"]\n\
+             deck {\n\
+                 for card in [cards] {\n\
+                     card { position: \"${card_index}\" first: \"yes\" if ${card_first} last: \"yes\" if ${card_last} \"${card}\" }\n\
+                 }\n\
+             }"
+        )
+        .unwrap(),
+        "<deck><card position=\"0\" first=\"yes\">monster</card>\
+         <card position=\"1\">spell</card>\
+         <card position=\"2\" last=\"yes\">trap</card></deck>"
+    );
+
+    assert_eq!(
+        to_xml("root { for i in 3..1 { item {} } }").unwrap(),
+        "<root/>"
+    );
+    assert!(matches!(
+        to_xml("def end: \"ten\"\nroot { for i in 1..$(end) { item {} } }"),
+        Err(MemlError::InvalidNumber { value, .. }) if value == "ten"
+    ));
+    assert!(matches!(
+        to_xml("root { for i in 0..1000000000000 {} }"),
+        Err(MemlError::LimitExceeded { .. })
+    ));
+}
+
+#[test]
+fn render() {
+    let error =
+        parse_str("def label(text): label { \"${text}\" }\nroot {\n    label(\"a\" \"b\")\n}")
+            .unwrap_err();
+
+    assert_eq!(
+        error.render(Style::Plain),
+        "error: function `label(text)` takes 1 argument(s) but 2 were supplied
+ --> 3:5
+  |
+3 |     label(\"a\" \"b\")
+  |     ^^^^^^^^^^^^^^ expected 1 argument(s)
+ ::: 1:5
+  |
+1 | def label(text): label { \"${text}\" }
+  |     ----- function defined here
+"
+    );
+
+    let colored = error.render(Style::Colored);
+    assert!(colored.starts_with("\x1b[1;31merror\x1b[0m"));
+    assert!(colored.contains("\x1b[1;31m^^^^^^^^^^^^^^ expected 1 argument(s)\x1b[0m"));
+
+    // Definitions exported by a manifest point to the manifest
+    let exports = parse_file("src/tests/meta.meml").unwrap().into_exports();
+    let error = parse_str_with_imports("use <function> caption\ncaption(\"a\" \"b\")", &exports)
+        .unwrap_err();
+    assert!(error
+        .render(Style::Plain)
+        .contains(" ::: src/tests/meta.meml:24:12\n"));
+
+    let Err(MemlError::Diagnostics(diagnostics)) = parse_manifest("src/tests/invalid.meml") else {
+        panic!("expected several errors");
+    };
+    let rendered = diagnostics.render(Style::Plain);
+    assert!(rendered.contains(" --> src/tests/invalid/second.meml:"));
+    assert!(rendered.ends_with("6 errors and 0 warnings"));
+}
+
+#[test]
+fn escapes() {
+    assert_eq!(
+        to_xml(
+            r#"root { a: "say \"hi\"\tnow" b: 'it\'s \$(not) a \${constant}' "a\\b\nc \u{1F600}" }"#
+        )
+        .unwrap(),
+        "<root a=\"say &quot;hi&quot;&#9;now\" b=\"it's $(not) a ${constant}\">a\\b\nc \u{1F600}</root>"
+    );
+    assert_eq!(
+        to_xml(
+            r###"def x: "1"
+root { a: r"$(x) \n" b: r#"say "hi""# "$(x)" }"###
+        )
+        .unwrap(),
+        "<root a=\"$(x) \\n\" b=\"say &quot;hi&quot;\">1</root>"
+    );
+
+    // Raw strings are text in elements as well, even if a constant is named `r`
+    assert_eq!(
+        to_xml(
+            r###"def x: "1"
+def r: r {}
+root { r"$(x) <" r#"say "hi""# !r"<b/>" r }"###
+        )
+        .unwrap(),
+        "<root>$(x) &lt;say \"hi\"<b/><r/></root>"
+    );
+
+    assert!(matches!(
+        to_xml(r#"root { "\q" }"#),
+        Err(MemlError::Syntax { .. })
+    ));
+    match to_xml(r#"root { "\u{D800}" }"#) {
+        Err(error @ MemlError::InvalidEscape { .. }) => {
+            assert_eq!(error.message(), r"invalid unicode escape `\u{D800}`")
+        }
+        other => panic!("unexpected result: {:?}", other),
+    }
+
+    // Control characters cannot be written to XML, not even escaped
+    match to_xml(r#"root { a: "x\0y" "\u{1}" }"#) {
+        Err(MemlError::Diagnostics(diagnostics)) => {
+            let messages = diagnostics
+                .iter()
+                .map(|diagnostic| diagnostic.error.message())
+                .collect::<Vec<String>>();
+            assert_eq!(
+                messages,
+                ["invalid character `U+0000`", "invalid character `U+0001`"]
+            );
+        }
+        other => panic!("unexpected result: {:?}", other),
+    }
+    assert_eq!(
+        to_xml(r#"root { a: "\t\n\r" "\u{FFFD}" }"#).unwrap(),
+        "<root a=\"&#9;&#10;&#13;\">\u{FFFD}</root>"
+    );
+
+    // Signatures show defaults the way they would be written
+    match to_xml(
+        r#"def f(a b="\"$\"\n"): f {}
+f()"#,
+    ) {
+        Err(MemlError::MissingArgument { signature, .. }) => {
+            assert_eq!(signature, r#"f(a b="\"\$\"\n")"#)
+        }
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
+
+#[test]
+fn xml_escaping() {
+    assert_eq!(
+        to_xml("card { text: \"<\\\"a\\\" & 'b'>\\n\" \"1 < 2 && 3 > 2\" }").unwrap(),
+        "<card text=\"&lt;&quot;a&quot; &amp; 'b'&gt;&#10;\">1 &lt; 2 &amp;&amp; 3 &gt; 2</card>"
+    );
+
+    // Trusted markup is written as is
+    let document = parse_str("text { !\"<b>bold</b> &amp; more\" }").unwrap();
+    assert!(matches!(document.elements()[0].nodes(), [Node::Raw(_)]));
+    assert_eq!(
+        document.elements()[0].as_xml(),
+        "<text><b>bold</b> &amp; more</text>"
+    );
+
+    match to_xml("1st {}") {
+        Err(error @ MemlError::InvalidName { .. }) => {
+            assert_eq!(error.message(), "`1st` is not a valid XML element name")
+        }
+        other => panic!("unexpected result: {:?}", other),
+    }
+    assert!(matches!(
+        to_xml("root { -attribute: \"value\" }"),
+        Err(MemlError::InvalidName { kind, .. }) if kind == "attribute"
+    ));
+    assert!(matches!(
+        to_xml("-ns:root {}"),
+        Err(MemlError::InvalidName { kind, .. }) if kind == "namespace prefix"
+    ));
+}
+
+#[test]
+fn multiline_strings() {
+    let exports = parse_file("src/tests/meta.meml").unwrap().into_exports();
+    let document = parse_file_with_imports("src/tests/in/multiline.meml", &exports).unwrap();
+    let card = &document.elements()[0];
+
+    assert_eq!(
+        card.attribute("description").unwrap(),
+        "hello world! card\n  * indented \"\"\"\n\nlast line"
+    );
+    let mut children = card.children();
+    assert_eq!(
+        children.next().unwrap().content(),
+        "folded lines\nnext paragraph"
+    );
+    assert_eq!(children.next().unwrap().content(), "first line\nsecond");
+
+    // Windows line breaks
+    assert_eq!(
+        to_xml("root { \"\"\"\r\n    a\r\n    b\r\n\"\"\" }").unwrap(),
+        "<root>a\nb</root>"
+    );
+}
+
+#[test]
+fn mixed_content() {
+    assert_eq!(
+        to_xml("p { \"Hello \" b { \"world\" } \"!\" }").unwrap(),
+        "<p>Hello <b>world</b>!</p>"
+    );
+    assert_eq!(
+        to_xml("p { !\"<br/>\" \"a & b\" i {} }").unwrap(),
+        "<p><br/>a &amp; b<i/></p>"
+    );
+
+    // Text in function bodies, loops and children passed to a function
+    assert_eq!(
+        to_xml(
+            "def link(url): a { href: \"${url}\" ${children} }\n\
+            p { for i in 1..=2 { \"${i} \" } link(\"/\") { \"home \" b { \"page\" } } }"
+        )
+        .unwrap(),
+        "<p>1 2 <a href=\"/\">home <b>page</b></a></p>"
+    );
+
+    let document = parse_str("p { \"a\" b {} \"c\" }").unwrap();
+    let p = &document.elements()[0];
+    assert_eq!(p.nodes().len(), 3);
+    assert_eq!(p.children().count(), 1);
+    assert_eq!(p.content(), "ac");
+
+    // Text has to be inside of an element
+    assert!(matches!(
+        to_xml("if \"a\" == \"a\" { \"text\" }"),
+        Err(MemlError::UnexpectedText { .. })
+    ));
+}
+
+#[test]
+fn namespaces() {
+    assert_eq!(
+        to_xml(
+            "namespace = \"https://example.com/default\"\n\
+             namespace ui = \"https://example.com/ui?a=1&b=2\"\n\
+             root { ui:button { xml:note {} } }\n\
+             ui:other {}"
+        )
+        .unwrap(),
+        "<root xmlns=\"https://example.com/default\" xmlns:ui=\"https://example.com/ui?a=1&amp;b=2\">\
+         <ui:button><xml:note/></ui:button></root>\
+         <ui:other xmlns=\"https://example.com/default\" xmlns:ui=\"https://example.com/ui?a=1&amp;b=2\"/>"
+    );
+
+    // Elements may still be called `namespace`
+    assert_eq!(to_xml("namespace {}").unwrap(), "<namespace/>");
+
+    match to_xml("root {\n    ui:button {}\n}") {
+        Err(error @ MemlError::UndefinedNamespace { .. }) => {
+            assert_eq!(error.location().unwrap().line, 2);
+            assert_eq!(
+                error.message(),
+                "undeclared namespace prefix `ui` (declare it with `namespace ui = \"...\"`)"
+            );
+        }
+        other => panic!("unexpected result: {:?}", other),
+    }
+    assert!(matches!(
+        to_xml("namespace 1ns = \"uri\""),
+        Err(MemlError::InvalidName { kind, .. }) if kind == "namespace prefix"
+    ));
+
+    // Declarations cannot make the output namespace-ill-formed
+    assert_eq!(
+        to_xml("namespace xml = \"http://www.w3.org/XML/1998/namespace\"\nroot {}").unwrap(),
+        "<root xmlns:xml=\"http://www.w3.org/XML/1998/namespace\"/>"
+    );
+    for source in [
+        "namespace xmlns = \"uri\"",
+        "namespace xml = \"uri\"",
+        "namespace ns = \"http://www.w3.org/XML/1998/namespace\"",
+        "namespace = \"http://www.w3.org/2000/xmlns/\"",
+        "namespace ns = \"a\"\nnamespace ns = \"b\"",
+        "namespace = \"a\"\nnamespace = \"a\"",
+        "root { xmlns: \"a\" }",
+        "root { xmlns:ns: \"a\" }",
+    ] {
+        assert!(
+            matches!(to_xml(source), Err(MemlError::InvalidNamespace { .. })),
+            "{}",
+            source
+        );
+    }
+    match to_xml("namespace ns = \"a\"\nnamespace ns = \"b\"") {
+        Err(error) => {
+            assert_eq!(error.location().unwrap().line, 2);
+            assert_eq!(
+                error.message(),
+                "invalid namespace declaration: namespace prefix `ns` is already declared in \
+                 this file"
+            );
+        }
+        other => panic!("unexpected result: {:?}", other),
+    }
+    assert_eq!(
+        to_xml("root { xmlns: \"a\" }").unwrap_err().message(),
+        "invalid namespace declaration: namespaces are declared with `namespace prefix = \"uri\"` \
+         instead of `xmlns` attributes"
+    );
+}
+
+#[test]
+fn namespaced_attributes() {
+    let document = parse_str(
+        "namespace xlink = \"http://www.w3.org/1999/xlink\"\n\
+         a { xlink:href: \"#top\" xml:lang: \"en\" text: \"top\" }",
+    )
+    .unwrap();
+    let a = &document.elements()[0];
+
+    assert_eq!(a.attribute("xlink:href"), Some("#top"));
+    assert_eq!(a.attribute("href"), None);
+    assert_eq!(a.attribute("text"), Some("top"));
+    let href = &a.attributes()[0];
+    assert_eq!((href.namespace(), href.name()), ("xlink", "href"));
+    assert_eq!(
+        a.as_xml(),
+        "<a xmlns:xlink=\"http://www.w3.org/1999/xlink\" xlink:href=\"#top\" xml:lang=\"en\" text=\"top\"/>"
+    );
+
+    // Prefixed attributes before children and conditions
+    assert_eq!(
+        to_xml("label { xml:space: \"preserve\" if \"a\" == \"a\" b {} }").unwrap(),
+        "<label xml:space=\"preserve\"><b/></label>"
+    );
+
+    match to_xml("a {\n    xlink:href: \"#top\"\n}") {
+        Err(MemlError::UndefinedNamespace { prefix, location }) => {
+            assert_eq!(prefix, "xlink");
+            assert_eq!(location.line, 2);
+        }
+        other => panic!("unexpected result: {:?}", other),
+    }
+    assert!(matches!(
+        to_xml("a { 1x:href: \"#top\" }"),
+        Err(MemlError::InvalidName { kind, .. }) if kind == "namespace prefix"
+    ));
+
+    // Attributes are unique by their qualified name
+    match to_xml("root { a: \"1\" a: \"2\" }") {
+        Err(error @ MemlError::DuplicateAttribute { .. }) => {
+            assert_eq!(error.message(), "attribute `a` is set more than once");
+            assert_eq!(error.location().unwrap().column, 15);
+        }
+        other => panic!("unexpected result: {:?}", other),
+    }
+    assert!(matches!(
+        to_xml("root { xml:lang: \"en\" xml:lang: \"de\" if \"a\" }"),
+        Err(MemlError::DuplicateAttribute { name, .. }) if name == "xml:lang"
+    ));
+    assert_eq!(
+        to_xml(
+            "namespace x = \"https://example.com/x\"\n\
+             root { a: \"1\" x:a: \"2\" a: \"3\" if \"\" }"
+        )
+        .unwrap(),
+        "<root xmlns:x=\"https://example.com/x\" a=\"1\" x:a=\"2\"/>"
+    );
+}
+
+#[test]
+fn json() {
+    let document = parse_str(
+        "namespace ui = \"https://example.com/ui\"\n\
+         ui:p { id: \"1\" xml:lang: \"en\" \"Hello \\\"\" b { \"world\" } \"!\\n\" }\n\
+         br {}",
+    )
+    .unwrap();
+
+    assert_eq!(
+        to_json(document.elements(), false),
+        "[{\"name\":\"p\",\"namespace\":\"ui\",\"attributes\":{\"id\":\"1\",\"xml:lang\":\"en\"},\
+         \"children\":[\"Hello \\\"\",{\"name\":\"b\",\"namespace\":\"\",\"attributes\":{},\
+         \"children\":[\"world\"],\"text\":\"world\"},\"!\\n\"],\"text\":\"Hello \\\"!\\n\"},\
+         {\"name\":\"br\",\"namespace\":\"\",\"attributes\":{},\"children\":[],\"text\":\"\"}]"
+    );
+    assert_eq!(
+        document.elements()[1].as_json(true),
+        "{\n  \"name\": \"br\",\n  \"namespace\": \"\",\n  \"attributes\": {},\n  \
+         \"children\": [],\n  \"text\": \"\"\n}"
+    );
+    assert_eq!(to_json(&[], true), "[]");
+}
+
+#[test]
+fn yaml_and_toml() {
+    let document = parse_str(
+        "namespace ui = \"https://example.com/ui\"\n\
+         window { title: \"Cards\" ui:box { xml:lang: \"en\" \"text\" } yes { \"no\" } empty {} }",
+    )
+    .unwrap();
+
+    assert_eq!(
+        to_yaml(document.elements()).unwrap(),
+        "window:\n  title: \"Cards\"\n  \"ui:box\":\n    \"xml:lang\": \"en\"\n    \"$text\": \"text\"\n  \
+         \"yes\": \"no\"\n  empty: \"\"\n"
+    );
+    assert_eq!(
+        to_toml(document.elements()).unwrap(),
+        "[window]\ntitle = \"Cards\"\nyes = \"no\"\nempty = \"\"\n\n\
+         [window.\"ui:box\"]\n\"xml:lang\" = \"en\"\n\"$text\" = \"text\"\n"
+    );
+    assert_eq!(
+        to_yaml(parse_str("a { b { \"x\" \"y\" } }").unwrap().elements()).unwrap(),
+        "a:\n  b: \"xy\"\n"
+    );
+    assert_eq!(to_yaml(&[]).unwrap(), "{}\n");
+    assert_eq!(to_toml(&[]).unwrap(), "");
+
+    // Arrays of tables in arrays of tables
+    let document = parse_str("a { b { c { d: \"1\" } c { d: \"2\" } } b {} }").unwrap();
+    assert_eq!(
+        to_yaml(document.elements()).unwrap(),
+        "a:\n  b:\n    - c:\n        - d: \"1\"\n        - d: \"2\"\n    - \"\"\n"
+    );
+
+    // Text and tables with the same name cannot be written in TOML
+    match to_toml(document.elements()) {
+        Err(error @ MemlError::UnsupportedOutput { .. }) => assert_eq!(
+            error.message(),
+            "cannot write `a.b` as TOML: elements with the same name must either all be text or all be tables"
+        ),
+        other => panic!("unexpected result: {:?}", other),
+    }
+    let document =
+        parse_str("a { b { c { d: \"1\" } c { d: \"2\" } } b { c { d: \"3\" } } }").unwrap();
+    assert_eq!(
+        to_toml(document.elements()).unwrap(),
+        "[a]\n\n[[a.b]]\n\n[[a.b.c]]\nd = \"1\"\n\n[[a.b.c]]\nd = \"2\"\n\n[[a.b]]\n\n[a.b.c]\nd = \"3\"\n"
+    );
+
+    assert!(matches!(
+        to_yaml(parse_str("a { b: \"1\" b {} }").unwrap().elements()),
+        Err(MemlError::UnsupportedOutput { key, .. }) if key == "a.b"
+    ));
+
+    // Mixed content would lose the position of its text
+    let document = parse_str("doc { p { \"Hello \" b { \"world\" } \"!\" } }").unwrap();
+    match to_yaml(document.elements()) {
+        Err(error @ MemlError::UnsupportedOutput { .. }) => assert_eq!(
+            error.message(),
+            "cannot write `doc.p` as YAML: text mixed with child elements loses its position"
+        ),
+        other => panic!("unexpected result: {:?}", other),
+    }
+    assert!(matches!(
+        to_toml(document.elements()),
+        Err(MemlError::UnsupportedOutput { key, .. }) if key == "doc.p"
+    ));
+}
+
+#[test]
+fn pretty_xml() {
+    let document = parse_str(
+        "namespace ui = \"https://example.com/ui\"\n\
+         interface { object { class: \"GtkLabel\" id: \"title_label\" property { name: \"label\" \
+         \"Hello \" b { \"world\" } } } empty {} }",
+    )
+    .unwrap();
+
+    assert_eq!(
+        crate::to_xml(document.elements(), &XmlOptions::pretty()),
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <interface xmlns:ui=\"https://example.com/ui\">\n  \
+         <object class=\"GtkLabel\" id=\"title_label\">\n    \
+         <property name=\"label\">Hello <b>world</b></property>\n  \
+         </object>\n  <empty/>\n</interface>\n"
+    );
+
+    // Long tags are wrapped, text is kept as is
+    let options = XmlOptions {
+        indent: Some(Indent::Tabs(1)),
+        line_ending: LineEnding::CrLf,
+        declaration: false,
+        max_width: 40,
+    };
+    assert_eq!(
+        crate::to_xml(document.elements(), &options),
+        "<interface xmlns:ui=\"https://example.com/ui\">\r\n\
+         \t<object\r\n\t\tclass=\"GtkLabel\"\r\n\t\tid=\"title_label\">\r\n\
+         \t\t<property name=\"label\">Hello <b>world</b></property>\r\n\
+         \t</object>\r\n\t<empty/>\r\n</interface>\r\n"
+    );
+
+    // The default options write everything on one line
+    assert_eq!(
+        crate::to_xml(document.elements(), &XmlOptions::default()),
+        document.elements()[0].as_xml()
+    );
+}
+
+#[test]
+fn serializers() {
+    let document = parse_str("deck { card { type: \"trap\" } card { \"a < b\" } }").unwrap();
+    let elements = document.elements();
+
+    let mut output = Vec::new();
+    XmlSerializer::default()
+        .serialize(elements, &mut output)
+        .unwrap();
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "<deck><card type=\"trap\"/><card>a &lt; b</card></deck>"
+    );
+
+    // `to_*` write the same as the serializers
+    let document = parse_str("deck { card { type: \"trap\" } card { type: \"spell\" } }").unwrap();
+    let elements = document.elements();
+    let serialize = |serializer: &dyn Serializer| {
+        let mut output = HashingWriter::new(Vec::new());
+        serializer.serialize(elements, &mut output).unwrap();
+        String::from_utf8(output.into_inner()).unwrap()
+    };
+    assert_eq!(
+        serialize(&XmlSerializer {
+            options: XmlOptions::pretty()
+        }),
+        crate::to_xml(elements, &XmlOptions::pretty())
+    );
+    assert_eq!(
+        serialize(&JsonSerializer { pretty: true }),
+        to_json(elements, true)
+    );
+    assert_eq!(serialize(&YamlSerializer), to_yaml(elements).unwrap());
+    assert_eq!(serialize(&TomlSerializer), to_toml(elements).unwrap());
+
+    // Hashes do not depend on how the output was split into writes
+    let mut whole = HashingWriter::new(io::sink());
+    whole.write_all(b"hello world").unwrap();
+    let mut parts = HashingWriter::new(io::sink());
+    parts.write_all(b"hello").unwrap();
+    parts.write_all(b" world").unwrap();
+    assert_eq!(whole.digest(), parts.digest());
+    parts.write_all(b"!").unwrap();
+    assert_ne!(whole.digest(), parts.digest());
+
+    // Write errors get the path of the output
+    let document = parse_str("deck { card { type: \"trap\" } }").unwrap();
+    let elements = document.elements();
+    let mut buffer = [0; 8];
+    match XmlSerializer::default().serialize(elements, &mut &mut buffer[..]) {
+        Err(error @ MemlError::Io { .. }) => match error.with_path(Path::new("out.xml")) {
+            MemlError::Io { path, .. } => assert_eq!(path, Path::new("out.xml")),
+            other => panic!("unexpected error: {:?}", other),
+        },
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
+
+/// Writes one line per element named by the `element` property.
+struct CardDb;
+
+struct CardDbSerializer {
+    element: String,
+}
+
+impl Serializer for CardDbSerializer {
+    fn serialize(&self, elements: &[Element], writer: &mut dyn Write) -> Result<(), MemlError> {
+        for element in elements.iter().flat_map(|element| element.children()) {
+            if element.name() == self.element {
+                writeln!(writer, "{}", element.attribute("type").unwrap_or_default())
+                    .map_err(|error| MemlError::io(Path::new(""), error))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Action for CardDb {
+    fn properties(&self) -> &[&str] {
+        &["element"]
+    }
+
+    fn serializer(&self, section: &Section) -> Result<Option<Box<dyn Serializer>>, String> {
+        let element = section.get("element").ok_or("No element specified.")?;
+        Ok(Some(Box::new(CardDbSerializer {
+            element: element.to_string(),
+        })))
+    }
+}
+
+#[test]
+fn custom_actions() {
+    let mut actions = Actions::default();
+    actions.register("card_db", CardDb);
+    parse_manifest_with_actions("src/tests/actions.meml", &actions).unwrap();
+
+    assert_eq!(
+        fs::read_to_string("src/tests/out/lists.db").unwrap(),
+        "monster\nspell\ntrap\n"
+    );
+    // The built-in actions are still available, and sections can repeat
+    // properties
+    assert!(fs::read_to_string("src/tests/out/lists.actions.xml")
+        .unwrap()
+        .starts_with("<deck>"));
+
+    // Without the custom action the section is invalid
+    let error = parse_manifest("src/tests/actions.meml").unwrap_err();
+    assert!(
+        matches!(
+            &error,
+            MemlError::InvalidManifest { section, message, .. }
+                if section == "card_db_test"
+                    && message == "Invalid action `card_db`. Possible values: \
+                        `json`, `none`, `toml`, `xml`, `yaml`"
+        ),
+        "{}",
+        error
+    );
+}
+
+#[test]
+fn target_path() {
+    let document = parse_str("s { target: \"out\" change_extension: \"pretty.xml\" }").unwrap();
+    let section = Section::new(&document.elements()[0], Path::new("root"));
+    let xml = Actions::default();
+    let xml = xml.get("xml").unwrap();
+
+    assert_eq!(
+        xml.target_path(&section, Path::new("in/cards.v2.meml")),
+        Path::new("root/out/cards.v2.pretty.xml")
+    );
+
+    // Without `change_extension` the extension stays `meml`
+    let document = parse_str("s { target: \"out\" }").unwrap();
+    let unchanged = Section::new(&document.elements()[0], Path::new("root"));
+    assert_eq!(
+        xml.target_path(&unchanged, Path::new("in/cards.meml")),
+        Path::new("root/out/cards.meml")
+    );
+
+    // File names do not have to be UTF-8
+    #[cfg(unix)]
+    {
+        use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+        assert_eq!(
+            xml.target_path(&section, Path::new(OsStr::from_bytes(b"in/\xff.meml"))),
+            Path::new("root/out").join(OsStr::from_bytes(b"\xff.pretty.xml"))
+        );
+    }
 }